@@ -0,0 +1,645 @@
+//! Assembler for the text syntax produced by [`crate::disasm`].
+//!
+//! A listing is a sequence of lines; everything after a `;` outside a
+//! string is a comment. Directives start with a dot:
+//!
+//! ```text
+//! .header version=0x51 format=0xE endian=big int_size=4 size_t=4
+//!         instruction_size=4 number_size=4 number_type=float
+//!         integral_flag=0 unk=0            ; optional, must come first
+//! .type 3 "MyStruct"                      ; file-level type constant
+//! .function main                          ; opens a prototype
+//! .upvalues 0
+//! .params 1
+//! .vararg 2
+//! .slots 6                                ; main chunk only
+//! .unk0 0                                 ; nested prototypes only
+//! .const "print"                          ; K(0), K(1), ... in order
+//! .const 0.5
+//! .const nil | true | false | 0x1234hk | lightuserdata 0x10
+//! .debug "@path.lua" "name" 3 6           ; source, name and line range
+//! .local "x" 0 4                          ; name, start pc, end pc
+//! .upvalue "cb"
+//!     0  [3]  GETGLOBAL  0 K(0)           ; pc and [line] are optional
+//! .function main.0                        ; nested prototype
+//! ...
+//! .end
+//! .end
+//! ```
+//!
+//! Instruction operands are listed in the order the disassembler prints
+//! them: A, then B and C (or Bx/sBx) when the opcode uses them. Constants
+//! are written `K(n)`; for RK operands this sets the constant bit.
+
+use crate::error::Error;
+use crate::opcodes::*;
+use crate::parser::parse_instructions;
+use crate::structs::*;
+use crate::writer::write_chunk;
+
+/// Assembles listing source into a binary chunk.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Error> {
+    let chunk = parse_assembly(source)?;
+    write_chunk(&chunk)
+}
+
+/// Parses listing source into a chunk with decoded instructions.
+pub fn parse_assembly(source: &str) -> Result<Chunk, Error> {
+    let mut assembler = Assembler {
+        header: LuaHeader {
+            version: 0x51,
+            format: 0xE,
+            endianness: LuaEndian::Big,
+            int_size: 4,
+            size_t: 4,
+            instruction_size: 4,
+            number_size: 4,
+            number_type: LuaNumberType::Float,
+            integral_flag: 0,
+            unk: 0,
+        },
+        types: Vec::new(),
+        stack: Vec::new(),
+        main: None,
+    };
+    let mut line_number = 0;
+    for (index, line) in source.lines().enumerate() {
+        line_number = index + 1;
+        assembler.line(line).map_err(|message| Error::Assemble {
+            line: line_number,
+            message,
+        })?;
+    }
+    let fail = |message: &str| Error::Assemble {
+        line: line_number,
+        message: message.to_string(),
+    };
+    if !assembler.stack.is_empty() {
+        return Err(fail("missing .end"));
+    }
+    let mut main_function = assembler.main.ok_or_else(|| fail("no .function"))?;
+    parse_instructions(&mut main_function)?;
+
+    Ok(Chunk {
+        header: assembler.header,
+        type_constants: TypeConstsSection {
+            constants_amount: assembler.types.len() as u32,
+            constants: assembler.types,
+        },
+        main_function,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+}
+
+/// Splits a line into words and quoted strings, dropping the comment.
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err("unterminated string".to_string()),
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => text.push('\n'),
+                        Some('r') => text.push('\r'),
+                        Some('t') => text.push('\t'),
+                        Some(d) if d.is_ascii_digit() => {
+                            let mut code = d.to_digit(10).unwrap_or(0);
+                            for _ in 0..2 {
+                                match chars.peek().and_then(|c| c.to_digit(10)) {
+                                    Some(digit) => {
+                                        code = code * 10 + digit;
+                                        chars.next();
+                                    }
+                                    None => break,
+                                }
+                            }
+                            let c = char::from_u32(code).ok_or("bad escape")?;
+                            text.push(c);
+                        }
+                        Some(other) => text.push(other),
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some(c) => text.push(c),
+                }
+            }
+            tokens.push(Token::Str(text));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_int(text: &str) -> Result<i64, String> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| format!("expected a number, found `{}`", text))?;
+    Ok(if negative { -value } else { value })
+}
+
+fn parse_u32(tokens: &[Token], index: usize) -> Result<u32, String> {
+    match tokens.get(index) {
+        Some(Token::Word(word)) => {
+            let value = parse_int(word)?;
+            u32::try_from(value).map_err(|_| format!("{} is out of range", value))
+        }
+        _ => Err("expected a number".to_string()),
+    }
+}
+
+fn parse_i32(tokens: &[Token], index: usize) -> Result<i32, String> {
+    match tokens.get(index) {
+        Some(Token::Word(word)) => {
+            let value = parse_int(word)?;
+            i32::try_from(value).map_err(|_| format!("{} is out of range", value))
+        }
+        _ => Err("expected a number".to_string()),
+    }
+}
+
+fn parse_string(tokens: &[Token], index: usize) -> Result<String, String> {
+    match tokens.get(index) {
+        Some(Token::Str(text)) => Ok(text.clone()),
+        _ => Err("expected a quoted string".to_string()),
+    }
+}
+
+fn opcode_by_name(name: &str) -> Option<OpCode> {
+    (0..OpCode::NUM_OPCODES as u8)
+        .filter_map(|value| OpCode::try_from(value).ok())
+        .find(|opcode| format!("{:?}", opcode) == name)
+}
+
+/// Size in bytes a string is stored with, including its NUL terminator.
+fn string_size(text: &str) -> u32 {
+    text.len() as u32 + 1
+}
+
+#[derive(Default)]
+struct FunctionBuilder {
+    unk0: u32,
+    upvalue_count: u32,
+    param_count: u32,
+    vararg: u8,
+    unk9: u32,
+    instructions: Vec<u32>,
+    lines: Vec<Option<u32>>,
+    constants: Vec<BungieConstant>,
+    debug: Option<(String, String, u32, u32)>,
+    locals: Vec<DebugLocal>,
+    upvalues: Vec<DebugUpvalue>,
+    children: Vec<FunctionBlock>,
+}
+
+impl FunctionBuilder {
+    fn finish(self, depth: u32) -> Result<FunctionBlock, String> {
+        let vararg = match self.vararg {
+            0 => VarArgFlags::None,
+            1 => VarArgFlags::Has,
+            2 => VarArgFlags::IsVar,
+            3 => VarArgFlags::Unk3,
+            4 => VarArgFlags::Needs,
+            other => return Err(format!("unsupported vararg flags {}", other)),
+        };
+        let has_debug_info = self.debug.is_some();
+        let debug_info = match self.debug {
+            Some((path, function_name, line_begin, line_end)) => {
+                let lines: Vec<u32> = if self.lines.iter().any(Option::is_some) {
+                    self.lines.iter().map(|line| line.unwrap_or(0)).collect()
+                } else {
+                    Vec::new()
+                };
+                DebugInfo {
+                    line_count: lines.len() as u32,
+                    locals_count: self.locals.len() as u32,
+                    upvalue_count_2: self.upvalues.len() as u32,
+                    line_begin,
+                    line_end,
+                    path_string_size: string_size(&path),
+                    path,
+                    function_string_size: string_size(&function_name),
+                    function_name,
+                    lines,
+                    locals: self.locals,
+                    upvalues: self.upvalues,
+                }
+            }
+            None => DebugInfo::default(),
+        };
+        Ok(FunctionBlock {
+            address: 0,
+            depth,
+            unk0: self.unk0,
+            upvalue_count: self.upvalue_count,
+            param_count: self.param_count,
+            vararg,
+            unk9: self.unk9,
+            instruction_count: self.instructions.len() as u32,
+            instructions_address: 0,
+            instructions: self
+                .instructions
+                .into_iter()
+                .map(|raw| LuaInstruction {
+                    raw,
+                    opcode: OpCode::default(),
+                    opmodes: OpModes::default(),
+                    args: Vec::new(),
+                })
+                .collect(),
+            consts: BungieConstsSection {
+                constants_amount: self.constants.len() as u32,
+                constants: self.constants,
+            },
+            has_debug_info,
+            debug_info,
+            function_count: self.children.len() as u32,
+            child_functions: self.children,
+        })
+    }
+}
+
+struct Assembler {
+    header: LuaHeader,
+    types: Vec<LuaConstant>,
+    stack: Vec<FunctionBuilder>,
+    main: Option<FunctionBlock>,
+}
+
+impl Assembler {
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        let tokens = tokenize(line)?;
+        let Some(Token::Word(first)) = tokens.first() else {
+            return match tokens.first() {
+                None => Ok(()),
+                Some(_) => Err("unexpected string".to_string()),
+            };
+        };
+        if first.starts_with('.') {
+            return self.directive(first, &tokens[1..]);
+        }
+        let func = self
+            .stack
+            .last_mut()
+            .ok_or("instruction outside of a .function")?;
+
+        // Skip the optional pc and [line] columns of a listing.
+        let mut rest = &tokens[..];
+        if matches!(rest.first(), Some(Token::Word(w)) if w.parse::<u32>().is_ok()) {
+            rest = &rest[1..];
+        }
+        let mut line_info = None;
+        if let Some(Token::Word(w)) = rest.first() {
+            if let Some(inner) = w.strip_prefix('[').and_then(|w| w.strip_suffix(']')) {
+                if inner != "-" {
+                    let line = parse_int(inner)?;
+                    let line =
+                        u32::try_from(line).map_err(|_| format!("{} is out of range", line))?;
+                    line_info = Some(line);
+                }
+                rest = &rest[1..];
+            }
+        }
+        let raw = encode_instruction(rest)?;
+        func.instructions.push(raw);
+        func.lines.push(line_info);
+        Ok(())
+    }
+
+    fn current(&mut self, directive: &str) -> Result<&mut FunctionBuilder, String> {
+        self.stack
+            .last_mut()
+            .ok_or_else(|| format!("{} outside of a .function", directive))
+    }
+
+    fn directive(&mut self, name: &str, args: &[Token]) -> Result<(), String> {
+        match name {
+            ".header" => {
+                if self.main.is_some() || !self.stack.is_empty() {
+                    return Err(".header must come before the first .function".to_string());
+                }
+                for arg in args {
+                    let Token::Word(pair) = arg else {
+                        return Err("expected key=value".to_string());
+                    };
+                    let (key, value) = pair.split_once('=').ok_or("expected key=value")?;
+                    self.header_field(key, value)?;
+                }
+                self.header.chunk_format().map_err(|err| err.to_string())?;
+            }
+            ".type" => {
+                let constant_type = parse_u32(args, 0)?;
+                let name = parse_string(args, 1)?;
+                self.types.push(LuaConstant {
+                    constant_type,
+                    string_size: string_size(&name),
+                    const_string: name,
+                });
+            }
+            ".function" => {
+                if self.main.is_some() {
+                    return Err("only one main .function is allowed".to_string());
+                }
+                self.stack.push(FunctionBuilder {
+                    vararg: if self.stack.is_empty() { 2 } else { 0 },
+                    ..Default::default()
+                });
+            }
+            ".end" => {
+                let builder = self.stack.pop().ok_or(".end without .function")?;
+                let func = builder.finish(self.stack.len() as u32)?;
+                match self.stack.last_mut() {
+                    Some(parent) => parent.children.push(func),
+                    None => self.main = Some(func),
+                }
+            }
+            ".upvalues" => self.current(name)?.upvalue_count = parse_u32(args, 0)?,
+            ".params" => self.current(name)?.param_count = parse_u32(args, 0)?,
+            ".vararg" => {
+                let flags = parse_u32(args, 0)?;
+                self.current(name)?.vararg =
+                    u8::try_from(flags).map_err(|_| format!("{} is out of range", flags))?;
+            }
+            ".slots" => self.current(name)?.unk9 = parse_u32(args, 0)?,
+            ".unk0" => self.current(name)?.unk0 = parse_u32(args, 0)?,
+            ".const" => {
+                let format = self.header.chunk_format().map_err(|err| err.to_string())?;
+                let constant = parse_constant(args, format)?;
+                self.current(name)?.constants.push(constant);
+            }
+            ".debug" => {
+                let path = parse_string(args, 0)?;
+                let function_name = parse_string(args, 1)?;
+                let line_begin = parse_u32(args, 2)?;
+                let line_end = parse_u32(args, 3)?;
+                self.current(name)?.debug = Some((path, function_name, line_begin, line_end));
+            }
+            ".local" => {
+                let local_name = parse_string(args, 0)?;
+                let start = parse_i32(args, 1)?;
+                let end = parse_i32(args, 2)?;
+                self.current(name)?.locals.push(DebugLocal {
+                    string_size: string_size(&local_name),
+                    local_name,
+                    start,
+                    end,
+                });
+            }
+            ".upvalue" => {
+                let upvalue = parse_string(args, 0)?;
+                self.current(name)?.upvalues.push(DebugUpvalue {
+                    string_size: string_size(&upvalue),
+                    string: upvalue,
+                });
+            }
+            _ => return Err(format!("unknown directive {}", name)),
+        }
+        Ok(())
+    }
+
+    fn header_field(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let byte = || -> Result<u8, String> {
+            u8::try_from(parse_int(value)?).map_err(|_| format!("{} is out of range", value))
+        };
+        let header = &mut self.header;
+        match key {
+            "version" => header.version = byte()?,
+            "format" => header.format = byte()?,
+            "endian" => {
+                header.endianness = match value {
+                    "big" => LuaEndian::Big,
+                    "little" => LuaEndian::Little,
+                    _ => return Err(format!("unknown endianness {}", value)),
+                }
+            }
+            "int_size" => header.int_size = byte()?,
+            "size_t" => header.size_t = byte()?,
+            "instruction_size" => header.instruction_size = byte()?,
+            "number_size" => header.number_size = byte()?,
+            "number_type" => {
+                header.number_type = match value {
+                    "float" => LuaNumberType::Float,
+                    "integer" => LuaNumberType::Integer,
+                    _ => return Err(format!("unknown number type {}", value)),
+                }
+            }
+            "integral_flag" => header.integral_flag = byte()?,
+            "unk" => header.unk = byte()?,
+            _ => return Err(format!("unknown header field {}", key)),
+        }
+        Ok(())
+    }
+}
+
+fn parse_constant(args: &[Token], format: ChunkFormat) -> Result<BungieConstant, String> {
+    let (constant_type, constant) = match args.first() {
+        Some(Token::Str(text)) => (
+            4,
+            BungieConstantEnum::String(BungieConstantString {
+                string_size: string_size(text),
+                const_string: text.clone(),
+            }),
+        ),
+        Some(Token::Word(word)) => match word.as_str() {
+            "nil" => (0, BungieConstantEnum::None),
+            "true" => (1, BungieConstantEnum::Bool(1)),
+            "false" => (1, BungieConstantEnum::Bool(0)),
+            "lightuserdata" => {
+                let Some(Token::Word(text)) = args.get(1) else {
+                    return Err("expected a lightuserdata value".to_string());
+                };
+                let hex = text.trim_start_matches("0x");
+                let value = u64::from_str_radix(hex, 16)
+                    .map_err(|_| format!("bad lightuserdata value {}", text))?;
+                if format.size_t != 8 && value > u32::MAX as u64 {
+                    return Err(format!("{} is out of range", text));
+                }
+                (2, BungieConstantEnum::LightUserData(value as i64))
+            }
+            word if word.starts_with("0x") && word.ends_with("hk") => {
+                let hex = &word[2..word.len() - 2];
+                let value =
+                    u64::from_str_radix(hex, 16).map_err(|_| format!("bad ui64 value {}", word))?;
+                (11, BungieConstantEnum::U64(value))
+            }
+            word => (3, BungieConstantEnum::Number(parse_number(word, format)?)),
+        },
+        None => return Err("expected a constant".to_string()),
+    };
+    Ok(BungieConstant {
+        constant_type,
+        constant,
+    })
+}
+
+fn parse_number(text: &str, format: ChunkFormat) -> Result<LuaNumber, String> {
+    let bad = || format!("bad number {}", text);
+    Ok(match (format.number_type, format.number_size) {
+        (LuaNumberType::Float, 8) => LuaNumber::F64(text.parse().map_err(|_| bad())?),
+        (LuaNumberType::Float, _) => LuaNumber::F32(text.parse().map_err(|_| bad())?),
+        (LuaNumberType::Integer, 8) => LuaNumber::I64(text.parse().map_err(|_| bad())?),
+        (LuaNumberType::Integer, _) => LuaNumber::I32(text.parse().map_err(|_| bad())?),
+    })
+}
+
+/// An operand as written: a plain number or a `K(n)` constant reference.
+fn parse_operand(token: &Token) -> Result<(bool, i64), String> {
+    let Token::Word(word) = token else {
+        return Err("unexpected string operand".to_string());
+    };
+    match word.strip_prefix("K(").and_then(|w| w.strip_suffix(')')) {
+        Some(index) => Ok((true, parse_int(index)?)),
+        None => Ok((false, parse_int(word)?)),
+    }
+}
+
+fn check_range(what: &str, value: i64, max: i64) -> Result<u32, String> {
+    if value < 0 || value > max {
+        return Err(format!("{} {} is out of range 0..={}", what, value, max));
+    }
+    Ok(value as u32)
+}
+
+/// Encodes a B or C operand of an iABC instruction.
+///
+/// B is only 8 bits wide, but REG_OR_CONST and OFFSET operands are decoded
+/// as 9 bits, so their top bit is the low bit of the opcode. That is what
+/// the `_BK` variants encode, and a listing has to agree with it.
+fn encode_bc(
+    opcode: OpCode,
+    mode: OpArgModeBC,
+    operand: (bool, i64),
+    is_b: bool,
+) -> Result<u32, String> {
+    let (is_const, value) = operand;
+    let name = if is_b { "B" } else { "C" };
+    if is_const && !matches!(mode, OpArgModeBC::REG_OR_CONST | OpArgModeBC::CONST) {
+        return Err(format!(
+            "operand {} of {:?} cannot be a constant",
+            name, opcode
+        ));
+    }
+    match mode {
+        OpArgModeBC::REG_OR_CONST if is_b => {
+            let bk = opcode as u8 & 1 == 1;
+            if bk != is_const {
+                let mut message = format!(
+                    "operand B of {:?} must be a {}",
+                    opcode,
+                    if bk { "constant" } else { "register" }
+                );
+                let other = format!("{:?}_BK", opcode);
+                if opcode_by_name(&other).is_some() {
+                    message += &format!(", use {} for a constant", other);
+                }
+                return Err(message);
+            }
+            check_range(name, value, 0xff)
+        }
+        OpArgModeBC::REG_OR_CONST => {
+            Ok(check_range(name, value, 0xff)? | if is_const { 0x100 } else { 0 })
+        }
+        OpArgModeBC::OFFSET if is_b => {
+            let value = check_range(name, value, 0x1ff)?;
+            if value >> 8 != opcode as u32 & 1 {
+                return Err(format!(
+                    "operand B of {:?} must be {} 0x100",
+                    opcode,
+                    if opcode as u8 & 1 == 1 {
+                        "at least"
+                    } else {
+                        "below"
+                    }
+                ));
+            }
+            Ok(value & 0xff)
+        }
+        OpArgModeBC::OFFSET => check_range(name, value, 0x1ff),
+        _ => check_range(name, value, 0xff),
+    }
+}
+
+fn encode_instruction(tokens: &[Token]) -> Result<u32, String> {
+    let Some(Token::Word(name)) = tokens.first() else {
+        return Err("expected an opcode".to_string());
+    };
+    let opcode = opcode_by_name(name).ok_or_else(|| format!("unknown opcode {}", name))?;
+    let modes = OP_MODES[opcode as usize];
+    let operands = tokens[1..]
+        .iter()
+        .map(parse_operand)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let uses_b = modes.arg_mode_b != OpArgModeBC::UNUSED;
+    let uses_c = modes.mode == OpMode::iABC && modes.arg_mode_c != OpArgModeBC::UNUSED;
+    let expected = 1 + uses_b as usize + uses_c as usize;
+    if operands.len() != expected {
+        return Err(format!(
+            "{:?} takes {} operands, found {}",
+            opcode,
+            expected,
+            operands.len()
+        ));
+    }
+
+    let (a_const, a) = operands[0];
+    if a_const {
+        return Err(format!("operand A of {:?} cannot be a constant", opcode));
+    }
+    let mut raw = (opcode as u32) << 25 | check_range("A", a, 0xff)?;
+    match modes.mode {
+        OpMode::iABC => {
+            let mut next = 1;
+            if uses_b {
+                raw |= encode_bc(opcode, modes.arg_mode_b, operands[next], true)? << 17;
+                next += 1;
+            }
+            if uses_c {
+                raw |= encode_bc(opcode, modes.arg_mode_c, operands[next], false)? << 8;
+            }
+        }
+        OpMode::iABx if uses_b => {
+            let (is_const, bx) = operands[1];
+            if is_const && modes.arg_mode_b != OpArgModeBC::CONST {
+                return Err(format!("operand Bx of {:?} cannot be a constant", opcode));
+            }
+            raw |= check_range("Bx", bx, 0x1ffff)? << 8;
+        }
+        OpMode::iAsBx if uses_b => {
+            let (_, sbx) = operands[1];
+            raw |= check_range("sBx", sbx + 0xffff, 0x1ffff)? << 8;
+        }
+        _ => {}
+    }
+    Ok(raw)
+}
@@ -0,0 +1,699 @@
+use crate::types::TypeRegistry;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+}
+
+impl BinOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Pow => "^",
+            BinOp::Concat => "..",
+            BinOp::Eq => "==",
+            BinOp::Ne => "~=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+        }
+    }
+
+    /// Binding power of the operator, as in the Lua 5.1 reference manual.
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le => 3,
+            BinOp::Concat => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::Mod => 6,
+            BinOp::Pow => 8,
+        }
+    }
+
+    fn is_right_assoc(self) -> bool {
+        matches!(self, BinOp::Concat | BinOp::Pow)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+impl UnOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnOp::Neg => "-",
+            UnOp::Not => "not ",
+            UnOp::Len => "#",
+        }
+    }
+}
+
+const UNARY_PRECEDENCE: u8 = 7;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    UI64(u64),
+    LightUserData(i64),
+    VarArg,
+    /// A register of the current function, named after the local variable
+    /// it holds.
+    Local(u32, String),
+    Upvalue(String),
+    Global(String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    /// `object:name(args)`
+    MethodCall(Box<Expr>, String, Vec<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Table(Vec<Expr>, Vec<(Expr, Expr)>),
+    /// `hmake name {}`, a new instance of an `hstructure`.
+    Struct(String),
+    /// `variable : type`, a local declared with a type checked on
+    /// assignment.
+    Typed(Box<Expr>, String),
+    Closure(Box<Function>),
+}
+
+impl Expr {
+    pub fn index(table: Expr, key: Expr) -> Expr {
+        Expr::Index(Box::new(table), Box::new(key))
+    }
+
+    pub fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    pub fn unary(op: UnOp, expr: Expr) -> Expr {
+        Expr::Unary(op, Box::new(expr))
+    }
+
+    /// Returns the logical negation of a condition, flipping comparisons
+    /// instead of wrapping them in `not` where possible.
+    pub fn negate(self) -> Expr {
+        match self {
+            Expr::Unary(UnOp::Not, e) => *e,
+            Expr::Binary(BinOp::Eq, l, r) => Expr::Binary(BinOp::Ne, l, r),
+            Expr::Binary(BinOp::Ne, l, r) => Expr::Binary(BinOp::Eq, l, r),
+            Expr::Bool(b) => Expr::Bool(!b),
+            e => Expr::unary(UnOp::Not, e),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, ..) => op.precedence(),
+            // A negative literal is written with a unary minus.
+            Expr::Unary(..) => UNARY_PRECEDENCE,
+            Expr::Number(n) if n.is_sign_negative() && !n.is_nan() => UNARY_PRECEDENCE,
+            _ => u8::MAX,
+        }
+    }
+
+    /// Whether the expression can be indexed or called without parentheses.
+    fn is_prefix(&self) -> bool {
+        matches!(
+            self,
+            Expr::Local(..)
+                | Expr::Upvalue(_)
+                | Expr::Global(_)
+                | Expr::Index(..)
+                | Expr::Call(..)
+                | Expr::MethodCall(..)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Assign(Vec<Expr>, Vec<Expr>),
+    /// `local targets = values`, with no values for a bare declaration.
+    Local(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    Return(Vec<Expr>),
+    If(Expr, Block, Block),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    /// `for var = start, limit, step do ... end`
+    NumericFor(Expr, Expr, Expr, Expr, Block),
+    /// `for vars in exprs do ... end`
+    GenericFor(Vec<Expr>, Vec<Expr>, Block),
+    Break,
+    Comment(String),
+    /// An `hstructure` declaration.
+    Structure(Structure),
+}
+
+pub type Block = Vec<Stmt>;
+
+/// A Havok Script structure type, declared with its slots in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Structure {
+    pub name: String,
+    pub slots: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Function {
+    pub name: Option<String>,
+    pub source: Option<SourceRange>,
+    pub params: Vec<Param>,
+    pub is_vararg: bool,
+    pub body: Block,
+}
+
+/// A named parameter, with the type it is checked against if it has one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub type_name: Option<String>,
+}
+
+impl std::fmt::Display for Param {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.type_name {
+            Some(type_name) => write!(f, "{} : {}", self.name, type_name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl Function {
+    /// Calls `visit` on every statement of the function and of the
+    /// closures it defines, each before the statements nested in it.
+    pub fn visit_stmts(&self, visit: &mut dyn FnMut(&Stmt)) {
+        visit_block(&self.body, visit);
+    }
+}
+
+fn visit_block(block: &Block, visit: &mut dyn FnMut(&Stmt)) {
+    for stmt in block {
+        visit(stmt);
+        match stmt {
+            Stmt::Assign(targets, values) | Stmt::Local(targets, values) => {
+                for expr in targets.iter().chain(values) {
+                    visit_expr(expr, visit);
+                }
+            }
+            Stmt::Call(expr) => visit_expr(expr, visit),
+            Stmt::Return(values) => values.iter().for_each(|e| visit_expr(e, visit)),
+            Stmt::If(cond, then_block, else_block) => {
+                visit_expr(cond, visit);
+                visit_block(then_block, visit);
+                visit_block(else_block, visit);
+            }
+            Stmt::While(cond, body) | Stmt::Repeat(body, cond) => {
+                visit_expr(cond, visit);
+                visit_block(body, visit);
+            }
+            Stmt::NumericFor(_, start, limit, step, body) => {
+                for expr in [start, limit, step] {
+                    visit_expr(expr, visit);
+                }
+                visit_block(body, visit);
+            }
+            Stmt::GenericFor(_, exprs, body) => {
+                exprs.iter().for_each(|e| visit_expr(e, visit));
+                visit_block(body, visit);
+            }
+            Stmt::Break | Stmt::Comment(_) | Stmt::Structure(_) => {}
+        }
+    }
+}
+
+/// Visits the statements of the closures in `expr`.
+fn visit_expr(expr: &Expr, visit: &mut dyn FnMut(&Stmt)) {
+    match expr {
+        Expr::Closure(func) => func.visit_stmts(visit),
+        Expr::Index(lhs, rhs) | Expr::Binary(_, lhs, rhs) => {
+            visit_expr(lhs, visit);
+            visit_expr(rhs, visit);
+        }
+        Expr::Unary(_, operand) => visit_expr(operand, visit),
+        Expr::Call(func, args) | Expr::MethodCall(func, _, args) => {
+            visit_expr(func, visit);
+            args.iter().for_each(|arg| visit_expr(arg, visit));
+        }
+        Expr::Table(array, hash) => {
+            array.iter().for_each(|e| visit_expr(e, visit));
+            for (key, value) in hash {
+                visit_expr(key, visit);
+                visit_expr(value, visit);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The part of the original source a function was compiled from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceRange {
+    pub path: String,
+    pub line_begin: u32,
+    pub line_end: u32,
+}
+
+impl std::fmt::Display for SourceRange {
+    /// `path:begin-end`, without the `@` marking a chunk name as a file
+    /// name. The main chunk spans no lines.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.path.strip_prefix('@').unwrap_or(&self.path))?;
+        match (self.line_begin, self.line_end) {
+            (0, _) => Ok(()),
+            (begin, end) if begin == end => write!(f, ":{}", begin),
+            (begin, end) => write!(f, ":{}-{}", begin, end),
+        }
+    }
+}
+
+pub fn is_identifier(s: &str) -> bool {
+    const KEYWORDS: [&str; 21] = [
+        "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in",
+        "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+    ];
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !KEYWORDS.contains(&s)
+}
+
+pub fn quote_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => {
+                let _ = write!(out, "\\{:03}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        "(0/0)".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "math.huge" } else { "-math.huge" }.to_string()
+    } else if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        format!("{}", n)
+    }
+}
+
+/// Pretty printer turning the decompiler AST into Lua source text.
+pub struct LuaWriter {
+    out: String,
+    indent: usize,
+}
+
+impl Default for LuaWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LuaWriter {
+    pub fn new() -> Self {
+        LuaWriter {
+            out: String::new(),
+            indent: 0,
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Writes the body of a main chunk at the top level, headed by the
+    /// file it was compiled from.
+    pub fn write_chunk(&mut self, func: &Function, types: &TypeRegistry) {
+        if let Some(source) = &func.source {
+            self.line(&format!("-- {}", source));
+        }
+        for entry in types.iter() {
+            self.line(&format!("-- type {}", entry));
+        }
+        self.write_block(&func.body);
+    }
+
+    pub fn write_block(&mut self, block: &Block) {
+        let mut stmts = block.iter().peekable();
+        while let Some(stmt) = stmts.next() {
+            // `local f` and `f = function` is how `local function f`
+            // compiles, with `f` in scope in its own body.
+            if let (Stmt::Local(targets, values), Some(Stmt::Assign(assigned, closure))) =
+                (stmt, stmts.peek())
+            {
+                if let ([target], [], [Expr::Closure(func)]) =
+                    (targets.as_slice(), values.as_slice(), closure.as_slice())
+                {
+                    if assigned == targets {
+                        let name = self.expr(target);
+                        self.write_function(&format!("local function {}", name), func, false);
+                        stmts.next();
+                        continue;
+                    }
+                }
+            }
+            self.write_stmt(stmt);
+        }
+    }
+
+    pub fn write_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            // `a.b.c = function` declares the function `a.b.c`.
+            Stmt::Assign(targets, values) if self.function_head(targets, values).is_some() => {
+                if let (Some((name, method)), [Expr::Closure(func)]) =
+                    (self.function_head(targets, values), values.as_slice())
+                {
+                    self.write_function(&format!("function {}", name), func, method);
+                }
+            }
+            Stmt::Assign(targets, values) => {
+                let line = format!("{} = {}", self.expr_list(targets), self.expr_list(values));
+                self.write_multiline(&line);
+            }
+            Stmt::Local(targets, values) if values.is_empty() => {
+                let line = format!("local {}", self.expr_list(targets));
+                self.line(&line);
+            }
+            Stmt::Local(targets, values) => {
+                let line = format!(
+                    "local {} = {}",
+                    self.expr_list(targets),
+                    self.expr_list(values)
+                );
+                self.write_multiline(&line);
+            }
+            Stmt::Call(call) => {
+                let line = self.expr(call);
+                self.write_multiline(&line);
+            }
+            Stmt::Return(values) if values.is_empty() => self.line("return"),
+            Stmt::Return(values) => {
+                let line = format!("return {}", self.expr_list(values));
+                self.write_multiline(&line);
+            }
+            Stmt::If(cond, then_block, else_block) => {
+                let line = format!("if {} then", self.expr(cond));
+                self.write_multiline(&line);
+                self.write_indented(then_block);
+                let mut else_block = else_block;
+                // An else branch holding nothing but another if is an elseif.
+                while let [Stmt::If(cond, then_block, next)] = else_block.as_slice() {
+                    let line = format!("elseif {} then", self.expr(cond));
+                    self.write_multiline(&line);
+                    self.write_indented(then_block);
+                    else_block = next;
+                }
+                if !else_block.is_empty() {
+                    self.line("else");
+                    self.write_indented(else_block);
+                }
+                self.line("end");
+            }
+            Stmt::While(cond, body) => {
+                let line = format!("while {} do", self.expr(cond));
+                self.write_multiline(&line);
+                self.write_indented(body);
+                self.line("end");
+            }
+            Stmt::Repeat(body, cond) => {
+                self.line("repeat");
+                self.write_indented(body);
+                let line = format!("until {}", self.expr(cond));
+                self.write_multiline(&line);
+            }
+            Stmt::NumericFor(var, start, limit, step, body) => {
+                let mut line = format!(
+                    "for {} = {}, {}",
+                    self.expr(var),
+                    self.expr(start),
+                    self.expr(limit)
+                );
+                if *step != Expr::Number(1.0) {
+                    let _ = write!(line, ", {}", self.expr(step));
+                }
+                line.push_str(" do");
+                self.write_multiline(&line);
+                self.write_indented(body);
+                self.line("end");
+            }
+            Stmt::GenericFor(vars, exprs, body) => {
+                let line = format!(
+                    "for {} in {} do",
+                    self.expr_list(vars),
+                    self.expr_list(exprs)
+                );
+                self.write_multiline(&line);
+                self.write_indented(body);
+                self.line("end");
+            }
+            Stmt::Break => self.line("break"),
+            Stmt::Comment(text) => {
+                for l in text.lines() {
+                    self.line(&format!("-- {}", l));
+                }
+            }
+            Stmt::Structure(structure) => {
+                self.line(&format!("hstructure {}", structure.name));
+                self.indent += 1;
+                for slot in &structure.slots {
+                    self.line(slot);
+                }
+                self.indent -= 1;
+                self.line("end");
+            }
+        }
+    }
+
+    fn write_indented(&mut self, block: &Block) {
+        self.indent += 1;
+        self.write_block(block);
+        self.indent -= 1;
+    }
+
+    /// Writes text that may contain embedded newlines (from nested
+    /// function bodies), indenting every line at the current level.
+    fn write_multiline(&mut self, text: &str) {
+        for l in text.lines() {
+            self.line(l);
+        }
+    }
+
+    fn expr_list(&self, exprs: &[Expr]) -> String {
+        exprs
+            .iter()
+            .map(|e| self.expr(e))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Nil => "nil".to_string(),
+            Expr::Bool(b) => b.to_string(),
+            Expr::Number(n) => format_number(*n),
+            Expr::String(s) => quote_string(s),
+            Expr::UI64(v) => format!("0x{:X}hk", v),
+            Expr::LightUserData(v) => format!("--[[lightuserdata]] 0x{:X}", v),
+            Expr::VarArg => "...".to_string(),
+            Expr::Local(_, name) => name.clone(),
+            Expr::Upvalue(name) => name.clone(),
+            Expr::Global(name) if is_identifier(name) => name.clone(),
+            Expr::Global(name) => format!("_G[{}]", quote_string(name)),
+            Expr::Index(table, key) => {
+                let table = self.prefix_expr(table);
+                match key.as_ref() {
+                    Expr::String(s) if is_identifier(s) => format!("{}.{}", table, s),
+                    key => format!("{}[{}]", table, self.expr(key)),
+                }
+            }
+            Expr::Call(func, args) => {
+                format!("{}({})", self.prefix_expr(func), self.expr_list(args))
+            }
+            Expr::MethodCall(object, name, args) => format!(
+                "{}:{}({})",
+                self.prefix_expr(object),
+                name,
+                self.expr_list(args)
+            ),
+            Expr::Binary(op, lhs, rhs) => {
+                let prec = op.precedence();
+                let (lhs_min, rhs_min) = if op.is_right_assoc() {
+                    (prec + 1, prec)
+                } else {
+                    (prec, prec + 1)
+                };
+                format!(
+                    "{} {} {}",
+                    self.expr_with_precedence(lhs, lhs_min),
+                    op.symbol(),
+                    self.expr_with_precedence(rhs, rhs_min)
+                )
+            }
+            Expr::Unary(op, operand) => {
+                let operand = self.expr_with_precedence(operand, UNARY_PRECEDENCE);
+                // `--` would start a comment.
+                let separator = if *op == UnOp::Neg && operand.starts_with('-') {
+                    " "
+                } else {
+                    ""
+                };
+                format!("{}{}{}", op.symbol(), separator, operand)
+            }
+            Expr::Table(array, hash) if array.is_empty() && hash.is_empty() => "{}".to_string(),
+            Expr::Table(array, hash) => {
+                let mut fields: Vec<String> = array.iter().map(|e| self.expr(e)).collect();
+                for (key, value) in hash {
+                    let field = match key {
+                        Expr::String(s) if is_identifier(s) => {
+                            format!("{} = {}", s, self.expr(value))
+                        }
+                        key => format!("[{}] = {}", self.expr(key), self.expr(value)),
+                    };
+                    fields.push(field);
+                }
+                format!("{{{}}}", fields.join(", "))
+            }
+            Expr::Struct(name) => format!("hmake {} {{}}", name),
+            Expr::Typed(variable, type_name) => format!("{} : {}", self.expr(variable), type_name),
+            Expr::Closure(func) => self.closure(func),
+        }
+    }
+
+    fn expr_with_precedence(&self, expr: &Expr, min: u8) -> String {
+        if expr.precedence() < min {
+            format!("({})", self.expr(expr))
+        } else {
+            self.expr(expr)
+        }
+    }
+
+    fn prefix_expr(&self, expr: &Expr) -> String {
+        if expr.is_prefix() {
+            self.expr(expr)
+        } else {
+            format!("({})", self.expr(expr))
+        }
+    }
+
+    /// The name a single closure assigned to `targets` is declared under,
+    /// with whether it is a method: `obj:name` when its first parameter is
+    /// `self`. Only a variable or a chain of fields of one has a name.
+    fn function_head(&self, targets: &[Expr], values: &[Expr]) -> Option<(String, bool)> {
+        let ([target], [Expr::Closure(func)]) = (targets, values) else {
+            return None;
+        };
+        if !is_name_path(target) {
+            return None;
+        }
+        match target {
+            Expr::Index(object, key)
+                if func.params.first().map(|param| param.name.as_str()) == Some("self") =>
+            {
+                let Expr::String(name) = key.as_ref() else {
+                    return None;
+                };
+                Some((format!("{}:{}", self.expr(object), name), true))
+            }
+            target => Some((self.expr(target), false)),
+        }
+    }
+
+    /// Writes a function statement opened by `head`, such as
+    /// `function name`, preceded by where the function was defined. A
+    /// method leaves its `self` parameter implicit.
+    fn write_function(&mut self, head: &str, func: &Function, method: bool) {
+        if let Some(source) = &func.source {
+            self.line(&format!("-- {}", source));
+        }
+        let params = params(func, method as usize);
+        self.line(&format!("{}({})", head, params));
+        self.write_indented(&func.body);
+        self.line("end");
+    }
+
+    fn closure(&self, func: &Function) -> String {
+        let mut inner = LuaWriter {
+            out: String::new(),
+            indent: 1,
+        };
+        inner.write_block(&func.body);
+        let comment = match (&func.name, &func.source) {
+            (Some(name), Some(source)) => format!(" -- {} ({})", name, source),
+            (Some(name), None) => format!(" -- {}", name),
+            (None, Some(source)) => format!(" -- {}", source),
+            (None, None) => String::new(),
+        };
+        format!("function({}){}\n{}end", params(func, 0), comment, inner.out)
+    }
+}
+
+/// Whether `expr` is a variable or a chain of named fields of one, as the
+/// name in a function statement must be.
+fn is_name_path(expr: &Expr) -> bool {
+    match expr {
+        Expr::Local(..) | Expr::Upvalue(_) => true,
+        Expr::Global(name) => is_identifier(name),
+        Expr::Index(object, key) => {
+            matches!(key.as_ref(), Expr::String(name) if is_identifier(name))
+                && is_name_path(object)
+        }
+        _ => false,
+    }
+}
+
+/// The parameter list of a function without its first `skip` parameters,
+/// `...` included.
+fn params(func: &Function, skip: usize) -> String {
+    let mut params: Vec<String> = func.params[skip.min(func.params.len())..]
+        .iter()
+        .map(Param::to_string)
+        .collect();
+    if func.is_vararg {
+        params.push("...".to_string());
+    }
+    params.join(", ")
+}
@@ -0,0 +1,257 @@
+//! Decompilation of whole directories of bytecode files, spread over all
+//! CPU cores, that carries on past files it cannot handle.
+
+use crate::ast::Stmt;
+use crate::decompiler::{lift_chunk, write_source};
+use crate::intrinsics::Intrinsics;
+use crate::load_chunk;
+use crate::output::output_path;
+use crate::types::TypeRegistry;
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, Once};
+
+/// The signature every chunk starts with, as checked by the header parser.
+const SIGNATURE: &[u8] = b"\x1bLua";
+
+/// What became of one input file.
+#[derive(Debug)]
+pub enum Outcome {
+    /// Fully decompiled and written to the path.
+    Ok(PathBuf),
+    /// Written to the path, but parts of it could only be kept as
+    /// disassembly, for the reason given.
+    Partial(PathBuf, String),
+    /// Nothing written, for the reason given.
+    Failed(String),
+}
+
+/// Lists the files under `dir`, recursively and in path order, that start
+/// with the Lua chunk signature. Files that cannot be read are skipped, and
+/// symbolic links to directories are not followed, so that a link to an
+/// ancestor cannot make the walk endless.
+pub fn find_chunks(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut chunks = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else if is_chunk(&path) {
+                chunks.push(path);
+            }
+        }
+    }
+    chunks.sort();
+    Ok(chunks)
+}
+
+fn is_chunk(path: &Path) -> bool {
+    let mut signature = [0; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|_| signature == SIGNATURE)
+}
+
+/// The source decompiled from one file, not written yet.
+struct Decompiled {
+    output: PathBuf,
+    source: String,
+    /// Regions left unstructured and instructions the lifter does not
+    /// support, the only things emitted as comments.
+    comments: usize,
+}
+
+fn decompile(
+    input: &Path,
+    outdir: Option<&Path>,
+    intrinsics: &Intrinsics,
+) -> Result<Decompiled, String> {
+    let data = std::fs::read(input).map_err(|err| format!("read failed: {}", err))?;
+    let chunk = load_chunk(&data).map_err(|err| format!("parse failed: {}", err))?;
+    let lifted = lift_chunk(&chunk, intrinsics);
+    let mut comments = 0;
+    lifted.visit_stmts(&mut |stmt| {
+        if let Stmt::Comment(_) = stmt {
+            comments += 1;
+        }
+    });
+    let output = match outdir {
+        Some(outdir) => output_path(outdir, &chunk, input),
+        None => input.with_extension("dec.lua"),
+    };
+    Ok(Decompiled {
+        output,
+        source: write_source(&lifted, &TypeRegistry::new(&chunk.type_constants)),
+        comments,
+    })
+}
+
+fn write(decompiled: Decompiled) -> Outcome {
+    let Decompiled {
+        output,
+        source,
+        comments,
+    } = decompiled;
+    let written = output
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&output, source));
+    match (written, comments) {
+        (Err(err), _) => Outcome::Failed(format!("writing {} failed: {}", output.display(), err)),
+        (Ok(()), 0) => Outcome::Ok(output),
+        (Ok(()), count) => {
+            Outcome::Partial(output, format!("{} region(s) kept as disassembly", count))
+        }
+    }
+}
+
+/// The output paths of a batch claimed so far, and the canonical paths of
+/// its inputs, which are never written over.
+struct Claims<'a> {
+    inputs: HashSet<PathBuf>,
+    written: Mutex<HashMap<PathBuf, &'a Path>>,
+}
+
+impl<'a> Claims<'a> {
+    fn new(inputs: impl IntoIterator<Item = &'a Path>) -> Self {
+        Claims {
+            inputs: inputs
+                .into_iter()
+                .filter_map(|input| input.canonicalize().ok())
+                .collect(),
+            written: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Claims `output` for `input`, or says why it must not be written.
+    fn claim(&self, output: &Path, input: &'a Path) -> Result<(), String> {
+        if output
+            .canonicalize()
+            .is_ok_and(|output| self.inputs.contains(&output))
+        {
+            return Err(format!("{} is an input", output.display()));
+        }
+        match self.written.lock().unwrap().entry(output.to_path_buf()) {
+            Entry::Occupied(first) => Err(format!(
+                "{} is already written from {}",
+                output.display(),
+                first.get().display()
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(input);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause")
+}
+
+thread_local! {
+    /// Whether this thread runs files for [`process_files`].
+    static IS_WORKER: Cell<bool> = const { Cell::new(false) };
+    /// Where the last panic on this thread happened.
+    static PANIC_LOCATION: Cell<Option<String>> = const { Cell::new(None) };
+}
+
+/// Replaces the panic hook, once, with one that keeps panics on worker
+/// threads quiet and records where they happened, so that they show up in
+/// the batch summary instead of in the middle of it. Panics anywhere else
+/// still go to the previous hook.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if IS_WORKER.with(Cell::get) {
+                let location = info.location().map(ToString::to_string);
+                PANIC_LOCATION.with(|cell| cell.set(location));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+/// Runs `process` on every file in `inputs`, on as many threads as there
+/// are CPU cores, and returns the outcomes in input order. A panic fails
+/// the file it happened on, with its message and location, and the other
+/// files carry on.
+pub fn process_files<'a, F>(inputs: &'a [PathBuf], process: F) -> Vec<Outcome>
+where
+    F: Fn(&'a Path) -> Outcome + Sync,
+{
+    install_panic_hook();
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |count| count.get())
+        .min(inputs.len());
+    let next = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<Option<Outcome>>> = Mutex::new(inputs.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                IS_WORKER.with(|cell| cell.set(true));
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(input) = inputs.get(index) else {
+                        break;
+                    };
+                    let outcome = std::panic::catch_unwind(AssertUnwindSafe(|| process(input)))
+                        .unwrap_or_else(|payload| {
+                            let message = panic_message(&*payload);
+                            Outcome::Failed(match PANIC_LOCATION.with(Cell::take) {
+                                Some(location) => format!("panicked at {location}: {message}"),
+                                None => format!("panicked: {message}"),
+                            })
+                        });
+                    outcomes.lock().unwrap()[index] = Some(outcome);
+                }
+            });
+        }
+    });
+
+    outcomes
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|outcome| outcome.unwrap_or_else(|| Outcome::Failed("not processed".to_string())))
+        .collect()
+}
+
+/// Decompiles every file in `inputs` with [`process_files`], writing the
+/// source of each as soon as it is decompiled, under `outdir` at its
+/// original script path, or next to the input as `<name>.dec.lua` if there
+/// is no `outdir`. No input is written over, and of several files with the
+/// same output path only the first to finish is written.
+pub fn decompile_files(
+    inputs: &[PathBuf],
+    outdir: Option<&Path>,
+    intrinsics: &Intrinsics,
+) -> Vec<Outcome> {
+    let claims = Claims::new(inputs.iter().map(PathBuf::as_path));
+    process_files(inputs, |input| {
+        let decompiled = match decompile(input, outdir, intrinsics) {
+            Ok(decompiled) => decompiled,
+            Err(reason) => return Outcome::Failed(reason),
+        };
+        match claims.claim(&decompiled.output, input) {
+            Ok(()) => write(decompiled),
+            Err(reason) => Outcome::Failed(reason),
+        }
+    })
+}
@@ -0,0 +1,520 @@
+use crate::opcodes::*;
+use crate::structs::*;
+use crate::types::TypeRegistry;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// How control reaches a successor block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Straight-line flow into the next block.
+    Fallthrough,
+    /// An unconditional jump (`JMP`, `FORPREP`, `LOADBOOL` with C set).
+    Jump,
+    /// Taken when the block's branch condition holds: the test does not
+    /// skip the following instruction, or `FORLOOP` continues.
+    True,
+    /// Taken when the branch condition fails.
+    False,
+}
+
+impl fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Jump => "jump",
+            EdgeKind::True => "true",
+            EdgeKind::False => "false",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+/// A maximal run of instructions `start..end` entered only at `start`.
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    pub successors: Vec<Edge>,
+    pub predecessors: Vec<usize>,
+}
+
+/// Control-flow graph of a single prototype. Block 0 is the entry; blocks
+/// are ordered by their first pc.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    /// Immediate dominator of each block; `None` for the entry and for
+    /// unreachable blocks.
+    pub idom: Vec<Option<usize>>,
+    /// Immediate post-dominator of each block; `None` when it is only
+    /// post-dominated by the function exit, or never reaches it.
+    pub ipdom: Vec<Option<usize>>,
+    block_of: Vec<usize>,
+    /// Tests whose following `JMP` was folded into the same block.
+    merged: BTreeSet<usize>,
+}
+
+fn is_test(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::EQ
+            | OpCode::EQ_BK
+            | OpCode::LT
+            | OpCode::LT_BK
+            | OpCode::LE
+            | OpCode::LE_BK
+            | OpCode::TEST
+            | OpCode::TEST_R1
+            | OpCode::TESTSET
+            | OpCode::TFORLOOP
+    )
+}
+
+fn is_return(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::RETURN
+            | OpCode::TAILCALL
+            | OpCode::TAILCALL_I
+            | OpCode::TAILCALL_C
+            | OpCode::TAILCALL_M
+            | OpCode::TAILCALL_I_R1
+    )
+}
+
+fn jump_target(pc: usize, offset: i32) -> usize {
+    (pc as i64 + 1 + offset as i64) as usize
+}
+
+/// Destinations of an explicit jump out of `pc`, not counting a test's
+/// fallthrough into the instruction it guards.
+fn explicit_targets(insts: &[LuaInstruction], pc: usize) -> Vec<usize> {
+    let inst = &insts[pc];
+    match inst.opcode {
+        OpCode::JMP | OpCode::FORPREP | OpCode::FORLOOP => vec![jump_target(pc, inst.sbx())],
+        OpCode::LOADBOOL if inst.c().value != 0 => vec![pc + 2],
+        opcode if is_test(opcode) => vec![pc + 2],
+        _ => vec![],
+    }
+}
+
+impl Cfg {
+    pub fn new(func: &FunctionBlock) -> Cfg {
+        let insts = &func.instructions;
+        let len = insts.len();
+
+        let mut targets = BTreeSet::new();
+        for pc in 0..len {
+            targets.extend(explicit_targets(insts, pc));
+        }
+        // A test guarding a JMP is a two-way branch on its own unless
+        // something else jumps straight to the JMP.
+        let merged: BTreeSet<usize> = (0..len)
+            .filter(|&pc| {
+                is_test(insts[pc].opcode)
+                    && insts.get(pc + 1).map(|i| i.opcode) == Some(OpCode::JMP)
+                    && !targets.contains(&(pc + 1))
+            })
+            .collect();
+
+        let mut leaders: BTreeSet<usize> = targets.iter().copied().filter(|&t| t < len).collect();
+        if len > 0 {
+            leaders.insert(0);
+        }
+        for pc in 0..len {
+            let opcode = insts[pc].opcode;
+            if merged.contains(&pc) {
+                continue;
+            }
+            let ends_block =
+                is_return(opcode) || is_test(opcode) || !explicit_targets(insts, pc).is_empty();
+            if ends_block && pc + 1 < len {
+                leaders.insert(pc + 1);
+            }
+        }
+
+        let starts: Vec<usize> = leaders.into_iter().collect();
+        let mut block_of = vec![0; len];
+        let mut blocks = Vec::with_capacity(starts.len());
+        for (index, &start) in starts.iter().enumerate() {
+            let end = starts.get(index + 1).copied().unwrap_or(len);
+            block_of[start..end].fill(index);
+            blocks.push(BasicBlock {
+                start,
+                end,
+                successors: Vec::new(),
+                predecessors: Vec::new(),
+            });
+        }
+
+        for block in blocks.iter_mut() {
+            let last = block.end - 1;
+            let mut edges = Vec::new();
+            let mut edge = |pc: usize, kind| {
+                if pc < len {
+                    edges.push(Edge {
+                        target: block_of[pc],
+                        kind,
+                    });
+                }
+            };
+            let inst = &insts[last];
+            match inst.opcode {
+                OpCode::JMP if last > 0 && merged.contains(&(last - 1)) => {
+                    edge(jump_target(last, inst.sbx()), EdgeKind::True);
+                    edge(last + 1, EdgeKind::False);
+                }
+                OpCode::JMP | OpCode::FORPREP => {
+                    edge(jump_target(last, inst.sbx()), EdgeKind::Jump)
+                }
+                OpCode::FORLOOP => {
+                    edge(jump_target(last, inst.sbx()), EdgeKind::True);
+                    edge(last + 1, EdgeKind::False);
+                }
+                OpCode::LOADBOOL if inst.c().value != 0 => edge(last + 2, EdgeKind::Jump),
+                opcode if is_test(opcode) => {
+                    edge(last + 1, EdgeKind::True);
+                    edge(last + 2, EdgeKind::False);
+                }
+                opcode if is_return(opcode) => {}
+                _ => edge(last + 1, EdgeKind::Fallthrough),
+            }
+            block.successors = edges;
+        }
+        for index in 0..blocks.len() {
+            for edge in blocks[index].successors.clone() {
+                blocks[edge.target].predecessors.push(index);
+            }
+        }
+
+        let successors: Vec<Vec<usize>> = blocks
+            .iter()
+            .map(|b| b.successors.iter().map(|e| e.target).collect())
+            .collect();
+        let idom = if blocks.is_empty() {
+            Vec::new()
+        } else {
+            immediate_dominators(&successors, 0)
+        };
+
+        // Post-dominators are dominators of the reversed graph, rooted at a
+        // virtual exit that every returning block flows into.
+        let exit = blocks.len();
+        let mut reversed = vec![Vec::new(); blocks.len() + 1];
+        for (index, succs) in successors.iter().enumerate() {
+            if succs.is_empty() {
+                reversed[exit].push(index);
+            }
+            for &succ in succs {
+                reversed[succ].push(index);
+            }
+        }
+        let mut ipdom = immediate_dominators(&reversed, exit);
+        ipdom.truncate(blocks.len());
+        for dom in ipdom.iter_mut() {
+            if *dom == Some(exit) {
+                *dom = None;
+            }
+        }
+
+        Cfg {
+            blocks,
+            idom,
+            ipdom,
+            block_of,
+            merged,
+        }
+    }
+
+    /// The block containing `pc`.
+    pub fn block_of(&self, pc: usize) -> usize {
+        self.block_of[pc]
+    }
+
+    /// The block containing `pc`, or `None` past the last instruction.
+    pub fn block_at(&self, pc: usize) -> Option<usize> {
+        self.block_of.get(pc).copied()
+    }
+
+    /// The instruction that decides which successor of `block` is taken:
+    /// the test of a merged test/JMP pair, otherwise the last instruction.
+    pub fn branch_pc(&self, block: usize) -> usize {
+        let last = self.blocks[block].end - 1;
+        if last > 0 && self.merged.contains(&(last - 1)) {
+            last - 1
+        } else {
+            last
+        }
+    }
+
+    /// Whether every path from the entry to `b` passes through `a`.
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        walk_up(&self.idom, a, b)
+    }
+
+    /// Whether every path from `b` to the function exit passes through `a`.
+    pub fn post_dominates(&self, a: usize, b: usize) -> bool {
+        walk_up(&self.ipdom, a, b)
+    }
+
+    /// Whether the edge `from -> to` closes a loop, i.e. `to` dominates
+    /// `from`.
+    pub fn is_back_edge(&self, from: usize, to: usize) -> bool {
+        self.dominates(to, from)
+    }
+
+    pub fn is_reachable(&self, block: usize) -> bool {
+        block == 0 || self.idom[block].is_some()
+    }
+
+    /// The closest block dominating both `a` and `b`.
+    pub fn common_dominator(&self, mut a: usize, b: usize) -> usize {
+        while !self.dominates(a, b) {
+            match self.idom[a] {
+                Some(parent) => a = parent,
+                None => return 0,
+            }
+        }
+        a
+    }
+
+    /// Cycles that can be entered at more than one block, which no Lua loop
+    /// compiles to. Each is returned as its set of blocks, reachable ones
+    /// only.
+    pub fn irreducible_cycles(&self) -> Vec<BTreeSet<usize>> {
+        let mut cycles = Vec::new();
+        for component in self.strongly_connected_components() {
+            let entries = component
+                .iter()
+                .filter(|&&block| {
+                    block == 0
+                        || self.blocks[block]
+                            .predecessors
+                            .iter()
+                            .any(|pred| !component.contains(pred) && self.is_reachable(*pred))
+                })
+                .count();
+            if entries > 1 {
+                cycles.push(component);
+            }
+        }
+        cycles
+    }
+
+    /// Kosaraju's algorithm over the reachable blocks, keeping only
+    /// components that contain a cycle.
+    fn strongly_connected_components(&self) -> Vec<BTreeSet<usize>> {
+        let count = self.blocks.len();
+        let mut order = Vec::with_capacity(count);
+        let mut visited = vec![false; count];
+        if count > 0 {
+            let mut stack = vec![(0, 0)];
+            visited[0] = true;
+            while let Some((block, child)) = stack.pop() {
+                match self.blocks[block].successors.get(child) {
+                    Some(edge) => {
+                        stack.push((block, child + 1));
+                        if !visited[edge.target] {
+                            visited[edge.target] = true;
+                            stack.push((edge.target, 0));
+                        }
+                    }
+                    None => order.push(block),
+                }
+            }
+        }
+
+        let mut assigned = vec![false; count];
+        let mut components = Vec::new();
+        for &root in order.iter().rev() {
+            if assigned[root] {
+                continue;
+            }
+            let mut component = BTreeSet::new();
+            let mut pending = vec![root];
+            assigned[root] = true;
+            while let Some(block) = pending.pop() {
+                component.insert(block);
+                for &pred in &self.blocks[block].predecessors {
+                    if visited[pred] && !assigned[pred] {
+                        assigned[pred] = true;
+                        pending.push(pred);
+                    }
+                }
+            }
+            let cyclic = component.len() > 1
+                || self.blocks[root]
+                    .successors
+                    .iter()
+                    .any(|edge| edge.target == root);
+            if cyclic {
+                components.push(component);
+            }
+        }
+        components
+    }
+}
+
+fn walk_up(tree: &[Option<usize>], a: usize, mut b: usize) -> bool {
+    loop {
+        if a == b {
+            return true;
+        }
+        match tree[b] {
+            Some(parent) => b = parent,
+            None => return false,
+        }
+    }
+}
+
+/// Cooper, Harvey and Kennedy's iterative dominator algorithm. Nodes not
+/// reachable from `entry` get no dominator.
+fn immediate_dominators(successors: &[Vec<usize>], entry: usize) -> Vec<Option<usize>> {
+    let count = successors.len();
+
+    // Reverse postorder, iteratively to cope with long instruction runs.
+    let mut order = Vec::with_capacity(count);
+    let mut visited = vec![false; count];
+    let mut stack = vec![(entry, 0)];
+    visited[entry] = true;
+    while let Some((node, child)) = stack.pop() {
+        if let Some(&next) = successors[node].get(child) {
+            stack.push((node, child + 1));
+            if !visited[next] {
+                visited[next] = true;
+                stack.push((next, 0));
+            }
+        } else {
+            order.push(node);
+        }
+    }
+    order.reverse();
+    let mut rpo_index = vec![usize::MAX; count];
+    for (index, &node) in order.iter().enumerate() {
+        rpo_index[node] = index;
+    }
+
+    let mut predecessors = vec![Vec::new(); count];
+    for (node, succs) in successors.iter().enumerate() {
+        for &succ in succs {
+            predecessors[succ].push(node);
+        }
+    }
+
+    let mut idom: Vec<Option<usize>> = vec![None; count];
+    idom[entry] = Some(entry);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in order.iter().skip(1) {
+            let mut new_idom = None;
+            for &pred in &predecessors[node] {
+                if idom[pred].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => pred,
+                    Some(current) => intersect(&idom, &rpo_index, pred, current),
+                });
+            }
+            if new_idom.is_some() && idom[node] != new_idom {
+                idom[node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idom[entry] = None;
+    idom
+}
+
+fn intersect(idom: &[Option<usize>], rpo_index: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idom[a].unwrap_or(a);
+        }
+        while rpo_index[b] > rpo_index[a] {
+            b = idom[b].unwrap_or(b);
+        }
+    }
+    a
+}
+
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let block_name = |block: Option<usize>| match block {
+            Some(block) => format!("B{}", block),
+            None => "-".to_string(),
+        };
+        for (index, block) in self.blocks.iter().enumerate() {
+            write!(f, "B{} [{}..{}]", index, block.start, block.end)?;
+            if !self.is_reachable(index) {
+                write!(f, " unreachable")?;
+            }
+            let successors = block
+                .successors
+                .iter()
+                .map(|edge| {
+                    let back = if self.is_back_edge(index, edge.target) {
+                        ", loop"
+                    } else {
+                        ""
+                    };
+                    format!("B{} ({}{})", edge.target, edge.kind, back)
+                })
+                .collect::<Vec<_>>();
+            let predecessors = block
+                .predecessors
+                .iter()
+                .map(|pred| format!("B{}", pred))
+                .collect::<Vec<_>>();
+            writeln!(
+                f,
+                " -> {} | preds: {} | idom: {} | ipdom: {}",
+                if successors.is_empty() {
+                    "exit".to_string()
+                } else {
+                    successors.join(", ")
+                },
+                if predecessors.is_empty() {
+                    "-".to_string()
+                } else {
+                    predecessors.join(", ")
+                },
+                block_name(self.idom[index]),
+                block_name(self.ipdom[index])
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the types `chunk` registers, then the CFG of its main function
+/// and every prototype nested in it, named as in the disassembly listing.
+pub fn cfg_listing(chunk: &Chunk) -> String {
+    let mut out = String::new();
+    for entry in TypeRegistry::new(&chunk.type_constants).iter() {
+        out.push_str(&format!("type {}\n", entry));
+    }
+    write_cfgs(&mut out, &chunk.main_function, &mut vec![]);
+    out
+}
+
+fn write_cfgs(out: &mut String, func: &FunctionBlock, path: &mut Vec<usize>) {
+    out.push_str(&format!(
+        "function {}\n",
+        crate::disasm::prototype_name(path)
+    ));
+    out.push_str(&Cfg::new(func).to_string());
+    out.push('\n');
+    for (index, child) in func.child_functions.iter().enumerate() {
+        path.push(index);
+        write_cfgs(out, child, path);
+        path.pop();
+    }
+}
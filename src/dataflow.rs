@@ -0,0 +1,478 @@
+use crate::ast::is_identifier;
+use crate::cfg::Cfg;
+use crate::opcodes::*;
+use crate::structs::*;
+use std::collections::BTreeSet;
+
+/// Registers one instruction reads and writes.
+pub(crate) struct Access {
+    /// Registers read, in evaluation order. `TESTSET` lists its operand
+    /// twice: it is both tested and copied.
+    pub reads: Vec<u32>,
+    /// Registers always overwritten.
+    pub writes: Vec<u32>,
+    /// The values read stay in their registers instead of being lifted
+    /// into an expression, so they must not be inlined.
+    pub opaque: bool,
+}
+
+fn register(arg: OpArg) -> Option<u32> {
+    (arg.mode == OpArgMode::REG).then_some(arg.value)
+}
+
+/// Whether `opcode` calls `R(A)` with the B - 1 arguments after it, or
+/// those up to the top of the stack if B is 0. Havok Script specializes
+/// `CALL` by what the compiler knows of the callee: `CALL_I` calls a Lua
+/// function, `CALL_C` a C function and `CALL_M` a method, with the object
+/// in `R(A+1)` as a `SELF` leaves it. All of them take the operands of
+/// `CALL`.
+fn is_call(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::CALL | OpCode::CALL_I | OpCode::CALL_C | OpCode::CALL_M | OpCode::CALL_I_R1
+    )
+}
+
+/// The results a call keeps plus one, as its operand C counts them: 0
+/// keeps them all, up to the top of the stack. `CALL_I_R1` is the
+/// `CALL_I` keeping exactly one result, in `R(A)`, whatever its C.
+pub(crate) fn call_results(inst: &LuaInstruction) -> u32 {
+    match inst.opcode {
+        OpCode::CALL_I_R1 => 2,
+        _ => inst.c().value,
+    }
+}
+
+/// Whether `opcode` returns all the results of a call made as by
+/// [`is_call`]. Operand C is unused, so `TAILCALL_I_R1` is no different
+/// from `TAILCALL_I`.
+fn is_tail_call(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::TAILCALL
+            | OpCode::TAILCALL_I
+            | OpCode::TAILCALL_C
+            | OpCode::TAILCALL_M
+            | OpCode::TAILCALL_I_R1
+    )
+}
+
+/// Whether `inst` leaves its results open up to the top of the stack.
+pub(crate) fn opens_results(inst: &LuaInstruction) -> bool {
+    (is_call(inst.opcode) && call_results(inst) == 0)
+        || (inst.opcode == OpCode::VARARG && inst.b().value == 0)
+}
+
+/// Whether `inst` takes its operands up to the top of the stack.
+fn takes_open(inst: &LuaInstruction) -> bool {
+    match inst.opcode {
+        OpCode::RETURN | OpCode::SETLIST => inst.b().value == 0,
+        opcode => (is_call(opcode) || is_tail_call(opcode)) && inst.b().value == 0,
+    }
+}
+
+/// Registers `from..from + count`, or up to and including `open`, the
+/// base of the last open results, when `count` is 0.
+fn span(from: u32, count: u32, open: Option<u32>) -> Vec<u32> {
+    match (count, open) {
+        (0, Some(top)) => (from..=top).collect(),
+        (0, None) => Vec::new(),
+        (count, _) => (from..from + count).collect(),
+    }
+}
+
+/// Def-use information for the registers of one prototype: which values
+/// are temporaries that the decompiler can inline into their only use.
+pub(crate) struct DefUse<'a> {
+    func: &'a FunctionBlock,
+    /// `MOVE`/`GETUPVAL` pseudo-instructions naming a closure's upvalues.
+    captures: Vec<bool>,
+    folds: Vec<bool>,
+}
+
+impl<'a> DefUse<'a> {
+    pub(crate) fn new(func: &'a FunctionBlock, cfg: &Cfg) -> Self {
+        let insts = &func.instructions;
+        let mut captures = vec![false; insts.len()];
+        for (pc, inst) in insts.iter().enumerate() {
+            if inst.opcode != OpCode::CLOSURE {
+                continue;
+            }
+            let Some(child) = func.child_functions.get(inst.b().value as usize) else {
+                continue;
+            };
+            for capture in pc + 1..(pc + 1 + child.upvalue_count as usize).min(insts.len()) {
+                if !matches!(insts[capture].opcode, OpCode::MOVE | OpCode::GETUPVAL) {
+                    break;
+                }
+                captures[capture] = true;
+            }
+        }
+        let mut def_use = DefUse {
+            func,
+            captures,
+            folds: vec![false; insts.len()],
+        };
+
+        let accesses: Vec<Vec<(usize, Access)>> = cfg
+            .blocks
+            .iter()
+            .map(|block| {
+                let mut open = None;
+                (block.start..block.end)
+                    .map(|pc| {
+                        let access = def_use.access(pc, open);
+                        open = next_open(&insts[pc], open);
+                        (pc, access)
+                    })
+                    .collect()
+            })
+            .collect();
+        let live_out = live_out(cfg, &accesses);
+        for (block, accesses) in accesses.iter().enumerate() {
+            for index in 0..accesses.len() {
+                let pc = accesses[index].0;
+                def_use.folds[pc] = def_use.is_foldable(&accesses[index..], &live_out[block]);
+            }
+        }
+        def_use
+    }
+
+    /// Whether the value computed at `pc` is a temporary inlined into its
+    /// only use. For `SELF` both the method and the object are inlined into
+    /// the call they prepare.
+    pub(crate) fn folds(&self, pc: usize) -> bool {
+        self.folds[pc]
+    }
+
+    /// The registers accessed by the instruction at `pc`, where `open` is
+    /// the base of the open results left by an earlier instruction.
+    pub(crate) fn access(&self, pc: usize, open: Option<u32>) -> Access {
+        let inst = &self.func.instructions[pc];
+        let (a, b, c) = (inst.a(), inst.b(), inst.c());
+        let regs =
+            |args: &[OpArg]| -> Vec<u32> { args.iter().filter_map(|&arg| register(arg)).collect() };
+        let mut opaque = false;
+        let (reads, writes) = match inst.opcode {
+            _ if self.captures[pc] => {
+                opaque = true;
+                let reads = match inst.opcode {
+                    OpCode::MOVE => vec![b.value],
+                    _ => Vec::new(),
+                };
+                (reads, Vec::new())
+            }
+            OpCode::MOVE => (vec![b.value], vec![a]),
+            OpCode::LOADK
+            | OpCode::LOADBOOL
+            | OpCode::GETGLOBAL
+            | OpCode::GETGLOBAL_MEM
+            | OpCode::GETUPVAL
+            | OpCode::NEWTABLE
+            | OpCode::NEWSTRUCT
+            | OpCode::CLOSURE => (Vec::new(), vec![a]),
+            OpCode::LOADNIL => (Vec::new(), (a..=b.value).collect()),
+            OpCode::GETFIELD | OpCode::GETFIELD_R1 | OpCode::GETFIELD_MM => {
+                (vec![b.value], vec![a])
+            }
+            OpCode::GETTABLE_S | OpCode::GETTABLE_N | OpCode::GETTABLE => (regs(&[b, c]), vec![a]),
+            OpCode::GETSLOT | OpCode::GETSLOTMT | OpCode::GETSLOT_D => (vec![b.value], vec![a]),
+            OpCode::SETSLOTN => (vec![a], Vec::new()),
+            OpCode::SETSLOTI | OpCode::SETSLOT | OpCode::SETSLOTS | OpCode::SETSLOTMT => {
+                let mut reads = vec![a];
+                reads.extend(register(c));
+                (reads, Vec::new())
+            }
+            OpCode::SETGLOBAL | OpCode::SETUPVAL | OpCode::SETUPVAL_R1 => (vec![a], Vec::new()),
+            OpCode::SETFIELD | OpCode::SETFIELD_R1 => {
+                let mut reads = vec![a];
+                reads.extend(register(c));
+                (reads, Vec::new())
+            }
+            OpCode::SETTABLE_S
+            | OpCode::SETTABLE_S_BK
+            | OpCode::SETTABLE_N
+            | OpCode::SETTABLE_N_BK
+            | OpCode::SETTABLE
+            | OpCode::SETTABLE_BK => {
+                let mut reads = vec![a];
+                reads.extend(regs(&[b, c]));
+                (reads, Vec::new())
+            }
+            OpCode::SELF => (regs(&[b, c]), vec![a, a + 1]),
+            OpCode::SELFSLOT | OpCode::SELFSLOTMT => (vec![b.value], vec![a, a + 1]),
+            OpCode::INTRINSIC_INDEX => (vec![b.value], vec![a]),
+            OpCode::INTRINSIC_LITERAL => (vec![a], vec![a]),
+            OpCode::INTRINSIC_NEWINDEX => (vec![a, c.value], Vec::new()),
+            OpCode::INTRINSIC_NEWINDEX_LITERAL => (vec![a, a + 1], Vec::new()),
+            OpCode::INTRINSIC_SELF => (vec![b.value], vec![a, a + 1]),
+            OpCode::INTRINSIC_SELF_LITERAL => (vec![a], vec![a, a + 1]),
+            OpCode::ADD
+            | OpCode::ADD_BK
+            | OpCode::SUB
+            | OpCode::SUB_BK
+            | OpCode::MUL
+            | OpCode::MUL_BK
+            | OpCode::DIV
+            | OpCode::DIV_BK
+            | OpCode::MOD
+            | OpCode::MOD_BK
+            | OpCode::POW
+            | OpCode::POW_BK => (regs(&[b, c]), vec![a]),
+            OpCode::UNM | OpCode::NOT | OpCode::NOT_R1 | OpCode::LEN => (vec![b.value], vec![a]),
+            OpCode::CONCAT => ((b.value..=c.value).collect(), vec![a]),
+            OpCode::JMP | OpCode::CLOSE | OpCode::DATA => (Vec::new(), Vec::new()),
+            // A type check only asserts what the register holds.
+            OpCode::CHECKTYPE | OpCode::CHECKTYPES | OpCode::CHECKTYPE_D => {
+                (Vec::new(), Vec::new())
+            }
+            OpCode::EQ
+            | OpCode::EQ_BK
+            | OpCode::LT
+            | OpCode::LT_BK
+            | OpCode::LE
+            | OpCode::LE_BK => (regs(&[b, c]), Vec::new()),
+            OpCode::TEST | OpCode::TEST_R1 => (vec![a], Vec::new()),
+            // The copy into A only happens on one path, so A is not
+            // overwritten for certain.
+            OpCode::TESTSET => (vec![b.value, b.value], Vec::new()),
+            opcode if is_call(opcode) || is_tail_call(opcode) => {
+                let mut reads = vec![a];
+                reads.extend(span(
+                    a + 1,
+                    b.value.saturating_sub(1),
+                    open.filter(|_| b.value == 0),
+                ));
+                let writes = match call_results(inst) {
+                    _ if is_tail_call(opcode) => Vec::new(),
+                    0 => vec![a],
+                    results => (a..a + results - 1).collect(),
+                };
+                (reads, writes)
+            }
+            // The RETURN after a tail call is never reached.
+            OpCode::RETURN if pc > 0 && is_tail_call(self.func.instructions[pc - 1].opcode) => {
+                (Vec::new(), Vec::new())
+            }
+            OpCode::RETURN => (
+                span(a, b.value.saturating_sub(1), open.filter(|_| b.value == 0)),
+                Vec::new(),
+            ),
+            // The loop takes over its control registers: they are only read
+            // again by FORLOOP as hidden state.
+            OpCode::FORPREP => (vec![a, a + 1, a + 2], vec![a, a + 1, a + 2]),
+            OpCode::FORLOOP => {
+                opaque = true;
+                (vec![a, a + 1, a + 2], vec![a, a + 3])
+            }
+            OpCode::TFORLOOP => {
+                opaque = true;
+                let vars = c.value.max(1);
+                (vec![a, a + 1, a + 2], (a + 3..a + 3 + vars).collect())
+            }
+            OpCode::SETLIST => {
+                let mut reads = vec![a];
+                reads.extend(span(a + 1, b.value, open.filter(|_| b.value == 0)));
+                (reads, Vec::new())
+            }
+            OpCode::VARARG => match b.value {
+                0 => (Vec::new(), vec![a]),
+                results => (Vec::new(), (a..a + results - 1).collect()),
+            },
+            _ => {
+                opaque = true;
+                let mut reads = Vec::new();
+                if inst.opmodes.arg_mode_a == OpArgModeA::REG {
+                    reads.push(a);
+                }
+                reads.extend(regs(&[b, c]));
+                (reads, Vec::new())
+            }
+        };
+        Access {
+            reads,
+            writes,
+            opaque,
+        }
+    }
+
+    /// Whether `reg` holds a local variable the source declared, either
+    /// right after `pc` writes it or at `use_pc`. The hidden control
+    /// variables of `for` loops do not count.
+    fn is_named_local(&self, reg: u32, pc: usize, use_pc: usize) -> bool {
+        reg < self.func.param_count
+            || [pc + 1, use_pc].iter().any(|&at| {
+                self.func
+                    .local_at(at, reg)
+                    .is_some_and(|local| !local.local_name.starts_with('('))
+            })
+    }
+
+    /// Whether the value written by the first of `accesses` is a temporary:
+    /// read exactly once before the end of its block or being overwritten,
+    /// and not held by a local.
+    fn is_foldable(&self, accesses: &[(usize, Access)], live_out: &BTreeSet<u32>) -> bool {
+        let (pc, access) = &accesses[0];
+        let inst = &self.func.instructions[*pc];
+        if access.opaque {
+            return false;
+        }
+        let value = match inst.opcode {
+            OpCode::LOADBOOL => inst.c().value == 0,
+            OpCode::VARARG => inst.b().value == 2,
+            OpCode::SELF
+            | OpCode::SELFSLOT
+            | OpCode::SELFSLOTMT
+            | OpCode::INTRINSIC_SELF
+            | OpCode::INTRINSIC_SELF_LITERAL => {
+                return self.is_method(accesses, live_out);
+            }
+            opcode if is_call(opcode) => call_results(inst) == 2,
+            OpCode::MOVE
+            | OpCode::LOADK
+            | OpCode::GETGLOBAL
+            | OpCode::GETGLOBAL_MEM
+            | OpCode::GETUPVAL
+            | OpCode::NEWTABLE
+            | OpCode::NEWSTRUCT
+            | OpCode::CLOSURE
+            | OpCode::GETFIELD
+            | OpCode::GETFIELD_R1
+            | OpCode::GETFIELD_MM
+            | OpCode::GETTABLE_S
+            | OpCode::GETTABLE_N
+            | OpCode::GETTABLE
+            | OpCode::GETSLOT
+            | OpCode::GETSLOTMT
+            | OpCode::GETSLOT_D
+            | OpCode::INTRINSIC_INDEX
+            | OpCode::INTRINSIC_LITERAL
+            | OpCode::ADD
+            | OpCode::ADD_BK
+            | OpCode::SUB
+            | OpCode::SUB_BK
+            | OpCode::MUL
+            | OpCode::MUL_BK
+            | OpCode::DIV
+            | OpCode::DIV_BK
+            | OpCode::MOD
+            | OpCode::MOD_BK
+            | OpCode::POW
+            | OpCode::POW_BK
+            | OpCode::UNM
+            | OpCode::NOT
+            | OpCode::NOT_R1
+            | OpCode::LEN
+            | OpCode::CONCAT => true,
+            _ => false,
+        };
+        if !value {
+            return false;
+        }
+        let reg = access.writes[0];
+        match single_use(accesses, reg, live_out) {
+            Some(use_pc) => !self.is_named_local(reg, *pc, use_pc),
+            None => false,
+        }
+    }
+
+    /// Whether a `SELF` at the start of `accesses` only prepares the call
+    /// that follows it, so that it can be written as `object:method(...)`.
+    fn is_method(&self, accesses: &[(usize, Access)], live_out: &BTreeSet<u32>) -> bool {
+        let (pc, _) = &accesses[0];
+        let inst = &self.func.instructions[*pc];
+        let a = inst.a();
+        // Slots and intrinsics are always named, unknown intrinsics by a
+        // pseudo-call.
+        let named = inst.opcode != OpCode::SELF
+            || inst.c().mode == OpArgMode::CONST
+                && matches!(
+                    self.func.consts.constants.get(inst.c().value as usize).map(|k| &k.constant),
+                    Some(BungieConstantEnum::String(s)) if is_identifier(&s.const_string)
+                );
+        let method = single_use(accesses, a, live_out);
+        let object = single_use(accesses, a + 1, live_out);
+        match (named, method, object) {
+            (true, Some(call), Some(object)) if call == object => {
+                let call_inst = &self.func.instructions[call];
+                (is_call(call_inst.opcode) || is_tail_call(call_inst.opcode))
+                    && call_inst.a() == a
+                    && call_inst.b().value != 1
+                    && !self.is_named_local(a, *pc, call)
+                    && !self.is_named_local(a + 1, *pc, call)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The base of the open results after `inst`, given those before it.
+fn next_open(inst: &LuaInstruction, open: Option<u32>) -> Option<u32> {
+    if opens_results(inst) {
+        Some(inst.a())
+    } else if takes_open(inst) {
+        None
+    } else {
+        open
+    }
+}
+
+/// The pc of the only instruction reading the value that the first of
+/// `accesses` writes to `reg`, if there is exactly one.
+fn single_use(accesses: &[(usize, Access)], reg: u32, live_out: &BTreeSet<u32>) -> Option<usize> {
+    let mut uses = None;
+    let mut count = 0;
+    for (pc, access) in &accesses[1..] {
+        let reads = access.reads.iter().filter(|&&r| r == reg).count();
+        if reads > 0 {
+            if access.opaque {
+                return None;
+            }
+            count += reads;
+            uses = Some(*pc);
+        }
+        if access.writes.contains(&reg) {
+            return uses.filter(|_| count == 1);
+        }
+    }
+    uses.filter(|_| count == 1 && !live_out.contains(&reg))
+}
+
+/// Registers live on exit from each block, by backward dataflow.
+fn live_out(cfg: &Cfg, accesses: &[Vec<(usize, Access)>]) -> Vec<BTreeSet<u32>> {
+    let count = cfg.blocks.len();
+    let mut used = vec![BTreeSet::new(); count];
+    let mut defined = vec![BTreeSet::new(); count];
+    for (block, accesses) in accesses.iter().enumerate() {
+        for (_, access) in accesses {
+            for reg in &access.reads {
+                if !defined[block].contains(reg) {
+                    used[block].insert(*reg);
+                }
+            }
+            defined[block].extend(access.writes.iter().copied());
+        }
+    }
+
+    let mut live_in: Vec<BTreeSet<u32>> = vec![BTreeSet::new(); count];
+    let mut live_out: Vec<BTreeSet<u32>> = vec![BTreeSet::new(); count];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in (0..count).rev() {
+            let out: BTreeSet<u32> = cfg.blocks[block]
+                .successors
+                .iter()
+                .flat_map(|edge| live_in[edge.target].iter().copied())
+                .collect();
+            let mut live = used[block].clone();
+            live.extend(out.difference(&defined[block]).copied());
+            if live != live_in[block] || out != live_out[block] {
+                live_in[block] = live;
+                live_out[block] = out;
+                changed = true;
+            }
+        }
+    }
+    live_out
+}
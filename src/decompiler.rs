@@ -0,0 +1,1223 @@
+use crate::ast::*;
+use crate::cfg::Cfg;
+use crate::dataflow::{call_results, opens_results, Access, DefUse};
+use crate::intrinsics::Intrinsics;
+use crate::opcodes::*;
+use crate::structs::*;
+use crate::structure::Structurer;
+use crate::types::TypeRegistry;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Number of array slots flushed by a single SETLIST (LFIELDS_PER_FLUSH).
+const FIELDS_PER_FLUSH: u32 = 50;
+
+/// Decompiles a main chunk into Lua source text. Without the chunk's type
+/// registry, structure types are named after their ids, and no intrinsic
+/// is known.
+pub fn decompile(func: &FunctionBlock) -> String {
+    write_source(&lift_function(func), &TypeRegistry::default())
+}
+
+/// Decompiles a chunk into Lua source text, naming the intrinsics it calls
+/// after `intrinsics`.
+pub fn decompile_chunk(chunk: &Chunk, intrinsics: &Intrinsics) -> String {
+    let types = TypeRegistry::new(&chunk.type_constants);
+    write_source(&lift_chunk(chunk, intrinsics), &types)
+}
+
+/// Writes a lifted main chunk as Lua source text, listing the types the
+/// chunk depends on after its source header.
+pub fn write_source(chunk: &Function, types: &TypeRegistry) -> String {
+    let mut writer = LuaWriter::new();
+    writer.write_chunk(chunk, types);
+    writer.finish()
+}
+
+/// Lifts the main chunk of `chunk` and all of its closures into the AST,
+/// headed by the `hstructure` declarations of the structure types they
+/// construct.
+pub fn lift_chunk(chunk: &Chunk, intrinsics: &Intrinsics) -> Function {
+    let types = TypeRegistry::new(&chunk.type_constants);
+    let context = Context {
+        types: &types,
+        intrinsics,
+    };
+    let mut main = lift(&chunk.main_function, context);
+    let declarations = structures(&chunk.main_function, &types)
+        .into_iter()
+        .map(Stmt::Structure);
+    main.body.splice(0..0, declarations);
+    main
+}
+
+/// Lifts a function prototype and all of its closures into the AST.
+pub fn lift_function(func: &FunctionBlock) -> Function {
+    let context = Context {
+        types: &TypeRegistry::default(),
+        intrinsics: &Intrinsics::default(),
+    };
+    lift(func, context)
+}
+
+/// What lifting a function needs to know besides its prototype.
+#[derive(Clone, Copy)]
+pub(crate) struct Context<'a> {
+    /// The chunk's types, naming structure and annotation types.
+    pub(crate) types: &'a TypeRegistry,
+    pub(crate) intrinsics: &'a Intrinsics,
+}
+
+fn lift(func: &FunctionBlock, context: Context) -> Function {
+    let cfg = Cfg::new(func);
+    let mut body = Structurer::new(func, &cfg, context).structure();
+    declare_synthesized(func, &mut body);
+    let annotations = type_annotations(func, context.types);
+    let debug = &func.debug_info;
+    let name = if func.has_debug_info && !debug.function_name.is_empty() {
+        Some(debug.function_name.clone())
+    } else {
+        None
+    };
+    let source = func.has_debug_info.then(|| SourceRange {
+        path: debug.path.clone(),
+        line_begin: debug.line_begin,
+        line_end: debug.line_end,
+    });
+    Function {
+        name,
+        source,
+        // The parameters are the first locals.
+        params: (0..func.param_count)
+            .map(|reg| Param {
+                name: local_name(func, 0, reg),
+                type_name: annotations.get(&(reg, 0)).cloned(),
+            })
+            .collect(),
+        is_vararg: func.is_vararg(),
+        body,
+    }
+}
+
+/// The name output for register `reg` read at `pc`: the local variable it
+/// holds, or `l_<depth>_<reg>` if it holds none or the debug info is
+/// stripped.
+pub fn local_name(func: &FunctionBlock, pc: usize, reg: u32) -> String {
+    func.local_at(pc, reg)
+        .map(|local| &local.local_name)
+        .filter(|name| is_identifier(name))
+        .cloned()
+        .unwrap_or_else(|| synthesized_name(func, reg))
+}
+
+fn synthesized_name(func: &FunctionBlock, reg: u32) -> String {
+    format!("l_{}_{}", func.depth, reg)
+}
+
+/// Declares the registers assigned under their `l_<depth>_<reg>` name,
+/// which no local declares, so that they do not become globals. Their
+/// scope is unknown, so each is declared in the outermost block of the
+/// function: by its first assignment if that is the first statement
+/// mentioning it, otherwise just before that statement.
+fn declare_synthesized(func: &FunctionBlock, body: &mut Block) {
+    let mut assigned = BTreeSet::new();
+    collect_synthesized(func, body, &mut assigned);
+    let mut declared_at: BTreeMap<usize, Vec<u32>> = BTreeMap::new();
+    for reg in assigned {
+        if let Some(index) = body.iter().position(|stmt| mentions(stmt, reg)) {
+            declared_at.entry(index).or_default().push(reg);
+        }
+    }
+    if declared_at.is_empty() {
+        return;
+    }
+    let mut declared = Vec::with_capacity(body.len() + declared_at.len());
+    for (index, stmt) in std::mem::take(body).into_iter().enumerate() {
+        let Some(regs) = declared_at.remove(&index) else {
+            declared.push(stmt);
+            continue;
+        };
+        match stmt {
+            Stmt::Assign(targets, values)
+                if targets.len() == regs.len()
+                    && targets.iter().all(
+                        |target| matches!(target, Expr::Local(reg, _) if regs.contains(reg)),
+                    )
+                    && !values
+                        .iter()
+                        .any(|value| regs.iter().any(|&reg| reads_register(value, reg))) =>
+            {
+                declared.push(Stmt::Local(targets, values));
+            }
+            stmt => {
+                let variables = regs
+                    .iter()
+                    .map(|&reg| Expr::Local(reg, synthesized_name(func, reg)))
+                    .collect();
+                declared.push(Stmt::Local(variables, Vec::new()));
+                declared.push(stmt);
+            }
+        }
+    }
+    *body = declared;
+}
+
+/// Collects the registers `block` assigns under their synthesized name,
+/// parameters aside.
+fn collect_synthesized(func: &FunctionBlock, block: &Block, regs: &mut BTreeSet<u32>) {
+    for stmt in block {
+        match stmt {
+            Stmt::Assign(targets, _) => {
+                for target in targets {
+                    if let Expr::Local(reg, name) = target {
+                        if *reg >= func.param_count && *name == synthesized_name(func, *reg) {
+                            regs.insert(*reg);
+                        }
+                    }
+                }
+            }
+            Stmt::If(_, then_block, else_block) => {
+                collect_synthesized(func, then_block, regs);
+                collect_synthesized(func, else_block, regs);
+            }
+            Stmt::While(_, body)
+            | Stmt::Repeat(body, _)
+            | Stmt::NumericFor(.., body)
+            | Stmt::GenericFor(.., body) => collect_synthesized(func, body, regs),
+            _ => {}
+        }
+    }
+}
+
+/// Whether `stmt` reads or writes register `reg` of the current function.
+fn mentions(stmt: &Stmt, reg: u32) -> bool {
+    let any = |exprs: &[Expr]| exprs.iter().any(|expr| reads_register(expr, reg));
+    let block = |block: &Block| block.iter().any(|stmt| mentions(stmt, reg));
+    match stmt {
+        Stmt::Assign(targets, values) | Stmt::Local(targets, values) => any(targets) || any(values),
+        Stmt::Call(expr) => reads_register(expr, reg),
+        Stmt::Return(values) => any(values),
+        Stmt::If(cond, then_block, else_block) => {
+            reads_register(cond, reg) || block(then_block) || block(else_block)
+        }
+        Stmt::While(cond, body) | Stmt::Repeat(body, cond) => {
+            reads_register(cond, reg) || block(body)
+        }
+        Stmt::NumericFor(var, start, limit, step, body) => {
+            [var, start, limit, step]
+                .iter()
+                .any(|expr| reads_register(expr, reg))
+                || block(body)
+        }
+        Stmt::GenericFor(vars, exprs, body) => any(vars) || any(exprs) || block(body),
+        Stmt::Break | Stmt::Comment(_) | Stmt::Structure(_) => false,
+    }
+}
+
+fn is_type_check(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::CHECKTYPE | OpCode::CHECKTYPES | OpCode::CHECKTYPE_D
+    )
+}
+
+/// The local checked by the type check at `pc`, as its register and the pc
+/// its scope starts at, if the check belongs to its declaration: it opens
+/// the function for a parameter, or follows the write declaring a local
+/// with nothing but other checks in between.
+fn checked_local(func: &FunctionBlock, pc: usize) -> Option<(u32, usize)> {
+    let insts = &func.instructions;
+    let reg = insts[pc].a();
+    let only_checks = |from: usize| {
+        insts[from.min(pc)..pc]
+            .iter()
+            .all(|i| is_type_check(i.opcode))
+    };
+    if reg < func.param_count {
+        return only_checks(0).then_some((reg, 0));
+    }
+    let local = func
+        .local_at(pc + 1, reg)
+        .or_else(|| func.local_at(pc, reg))?;
+    let start = local.start as usize;
+    only_checks(start).then_some((reg, start))
+}
+
+/// The types of the locals and parameters of `func` that are checked on
+/// declaration, by register and the pc their scope starts at.
+fn type_annotations(func: &FunctionBlock, types: &TypeRegistry) -> BTreeMap<(u32, usize), String> {
+    let mut annotations = BTreeMap::new();
+    for (pc, inst) in func.instructions.iter().enumerate() {
+        let type_name = match inst.opcode {
+            OpCode::CHECKTYPE | OpCode::CHECKTYPE_D => types.annotation(inst.b().value),
+            OpCode::CHECKTYPES => types.struct_name(inst.b().value),
+            _ => continue,
+        };
+        if let Some(local) = checked_local(func, pc) {
+            annotations.insert(local, type_name);
+        }
+    }
+    annotations
+}
+
+/// The name output for slot `index` of a structure; the bytecode only
+/// records slot indices.
+pub fn slot_name(index: u32) -> String {
+    format!("slot_{}", index)
+}
+
+/// The structure types constructed by `func` and its closures, in type id
+/// order, each with as many slots as the largest `NEWSTRUCT` of it gives.
+/// The slot types are not decoded, so the slots are left untyped.
+fn structures(func: &FunctionBlock, types: &TypeRegistry) -> Vec<Structure> {
+    fn collect(func: &FunctionBlock, slots: &mut BTreeMap<u32, u32>) {
+        for inst in &func.instructions {
+            if inst.opcode == OpCode::NEWSTRUCT {
+                let count = slots.entry(inst.b().value).or_default();
+                *count = (*count).max(inst.c().value);
+            }
+        }
+        for child in &func.child_functions {
+            collect(child, slots);
+        }
+    }
+    let mut slots = BTreeMap::new();
+    collect(func, &mut slots);
+    slots
+        .into_iter()
+        .map(|(id, count)| Structure {
+            name: types.struct_name(id),
+            slots: (0..count).map(slot_name).collect(),
+        })
+        .collect()
+}
+
+/// The name output for upvalue `index`: its name in the debug info, or
+/// `upv_<index>` if the debug info is stripped.
+pub fn upvalue_name(func: &FunctionBlock, index: u32) -> String {
+    func.debug_info
+        .upvalues
+        .get(index as usize)
+        .filter(|_| func.has_debug_info)
+        .map(|upvalue| upvalue.string.clone())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("upv_{}", index))
+}
+
+/// A value computed by an instruction but not yet emitted, waiting to be
+/// inlined into the instruction that uses it.
+enum Pending {
+    /// A temporary in `reg` with a single use, computed at `pc`.
+    Value { reg: u32, pc: usize, expr: Expr },
+    /// A `SELF` at `pc` that loaded `object.name` into `base` and `object`
+    /// into `base + 1` for the call that follows.
+    Method {
+        base: u32,
+        pc: usize,
+        object: Expr,
+        name: String,
+    },
+    /// A call or VARARG whose results run up to the top of the stack and
+    /// are consumed by the next instruction with a B operand of 0.
+    Open { base: u32, expr: Expr },
+}
+
+impl Pending {
+    fn registers(&self) -> Vec<u32> {
+        match self {
+            Pending::Value { reg, .. } => vec![*reg],
+            Pending::Method { base, .. } => vec![*base, base + 1],
+            Pending::Open { base, .. } => vec![*base],
+        }
+    }
+
+    fn reads_register(&self, reg: u32) -> bool {
+        match self {
+            Pending::Value { expr, .. } | Pending::Open { expr, .. } => reads_register(expr, reg),
+            Pending::Method { object, .. } => reads_register(object, reg),
+        }
+    }
+}
+
+/// Whether evaluating `expr` reads register `reg` of the current function.
+fn reads_register(expr: &Expr, reg: u32) -> bool {
+    match expr {
+        Expr::Local(r, _) => *r == reg,
+        Expr::Index(lhs, rhs) | Expr::Binary(_, lhs, rhs) => {
+            reads_register(lhs, reg) || reads_register(rhs, reg)
+        }
+        Expr::Unary(_, operand) => reads_register(operand, reg),
+        Expr::Call(func, args) | Expr::MethodCall(func, _, args) => {
+            reads_register(func, reg) || args.iter().any(|arg| reads_register(arg, reg))
+        }
+        Expr::Table(array, hash) => {
+            array.iter().any(|e| reads_register(e, reg))
+                || hash
+                    .iter()
+                    .any(|(k, v)| reads_register(k, reg) || reads_register(v, reg))
+        }
+        _ => false,
+    }
+}
+
+/// Lifts straight-line instructions into statements. Jumps, tests and
+/// loop instructions produce nothing here; the structurer turns them into
+/// control statements.
+///
+/// Temporaries that [`DefUse`] finds have a single use are not assigned
+/// but kept pending and inlined into the instruction using them, as long
+/// as that keeps everything evaluated in the original order.
+///
+/// Registers are named after the local variable they hold, and the write
+/// a local's scope starts after becomes its `local` declaration.
+pub(crate) struct Lifter<'a> {
+    func: &'a FunctionBlock,
+    context: Context<'a>,
+    /// The types locals are declared with, by register and start pc.
+    annotations: BTreeMap<(u32, usize), String>,
+    cfg: &'a Cfg,
+    def_use: DefUse<'a>,
+    /// The pc of the instruction being lifted.
+    pc: usize,
+    /// Values not emitted yet, in the order they were computed.
+    pending: Vec<Pending>,
+    /// Pending values consumed by the instruction being lifted.
+    taken: Vec<Pending>,
+    /// Whether the value written by the instruction being lifted is kept
+    /// pending.
+    defer: bool,
+    /// The condition of the last test lifted, with its pc.
+    branch: Option<(usize, Expr)>,
+    /// Start, limit and step of the last `FORPREP` lifted, with its pc.
+    for_prep: Option<(usize, [Expr; 3])>,
+    /// Locals declared so far, by register and start pc.
+    declared: BTreeSet<(u32, usize)>,
+    /// The start pc of the locals the last statement of `body` declares,
+    /// so that declarations of one statement are merged.
+    declaring: Option<usize>,
+    body: Block,
+}
+
+impl<'a> Lifter<'a> {
+    pub(crate) fn new(func: &'a FunctionBlock, cfg: &'a Cfg, context: Context<'a>) -> Self {
+        Lifter {
+            func,
+            context,
+            annotations: type_annotations(func, context.types),
+            cfg,
+            def_use: DefUse::new(func, cfg),
+            pc: 0,
+            pending: Vec::new(),
+            taken: Vec::new(),
+            defer: false,
+            branch: None,
+            for_prep: None,
+            declared: BTreeSet::new(),
+            declaring: None,
+            body: Vec::new(),
+        }
+    }
+
+    /// Lifts the instructions `start..end` of a basic block.
+    pub(crate) fn lift_range(&mut self, start: usize, end: usize) -> Block {
+        let mut pc = start;
+        while pc < end {
+            self.declare_locals(pc);
+            pc = self.lift_instruction(pc);
+        }
+        self.flush(self.pending.len());
+        std::mem::take(&mut self.body)
+    }
+
+    /// Emits the first `count` pending values as statements.
+    fn flush(&mut self, count: usize) {
+        let flushed: Vec<Pending> = self.pending.drain(..count).collect();
+        for pending in flushed {
+            self.emit(pending);
+        }
+    }
+
+    /// Emits the statements computing a pending value that cannot be
+    /// inlined.
+    fn emit(&mut self, pending: Pending) {
+        match pending {
+            Pending::Value { reg, pc, expr } => self.store(pc, &[reg], vec![expr]),
+            Pending::Method {
+                base,
+                pc,
+                object,
+                name,
+            } => self.store_self(pc, base, object, Expr::String(name)),
+            // Unused results of a call; a VARARG has no effect.
+            Pending::Open {
+                expr: Expr::VarArg, ..
+            } => {}
+            Pending::Open { expr, .. } => self.body.push(Stmt::Call(expr)),
+        }
+    }
+
+    /// Takes the pending values the instruction about to be lifted reads,
+    /// emitting those that cannot be inlined into it. Only the most recent
+    /// values can be inlined, and only if they are read in the order they
+    /// were computed; everything computed earlier is emitted before the
+    /// instruction unless it is itself kept pending.
+    fn take(&mut self, access: &Access) {
+        let mut split = self.pending.len();
+        while split > 0
+            && !access.opaque
+            && self.pending[split - 1]
+                .registers()
+                .iter()
+                .all(|reg| access.reads.contains(reg))
+        {
+            split -= 1;
+        }
+        let positions: Vec<Option<usize>> = self.pending[split..]
+            .iter()
+            .map(|pending| {
+                let first = pending.registers()[0];
+                access.reads.iter().position(|&reg| reg == first)
+            })
+            .collect();
+        if !positions.windows(2).all(|pair| pair[0] < pair[1]) {
+            split = self.pending.len();
+        }
+        self.taken = self.pending.split_off(split);
+
+        let flush = if self.defer {
+            // A value kept pending only needs what it reads or overwrites
+            // out of the way.
+            self.pending
+                .iter()
+                .rposition(|pending| {
+                    pending
+                        .registers()
+                        .iter()
+                        .any(|reg| access.reads.contains(reg) || access.writes.contains(reg))
+                        || access.writes.iter().any(|&reg| pending.reads_register(reg))
+                })
+                .map_or(0, |last| last + 1)
+        } else {
+            self.pending.len()
+        };
+        self.flush(flush);
+    }
+
+    /// The value of register `reg`: its pending expression if the current
+    /// instruction took one, the variable it holds otherwise.
+    fn reg(&mut self, reg: u32) -> Expr {
+        let taken = self
+            .taken
+            .iter()
+            .position(|pending| matches!(pending, Pending::Value { reg: r, .. } if *r == reg));
+        if let Some(Pending::Value { expr, .. }) = taken.map(|index| self.taken.remove(index)) {
+            return expr;
+        }
+        self.variable(reg, self.pc)
+    }
+
+    /// The variable register `reg` holds when read at `pc`.
+    pub(crate) fn variable(&self, reg: u32, pc: usize) -> Expr {
+        Expr::Local(reg, local_name(self.func, pc, reg))
+    }
+
+    /// The variable a write to register `reg` at `pc` stores into.
+    fn target(&self, reg: u32, pc: usize) -> Expr {
+        let name = self
+            .written_local(reg, pc)
+            .map(|(local, _)| &local.local_name)
+            .filter(|name| is_identifier(name))
+            .cloned()
+            .unwrap_or_else(|| synthesized_name(self.func, reg));
+        Expr::Local(reg, name)
+    }
+
+    /// The local a write to register `reg` at `pc` stores into, and
+    /// whether the write declares it. A local's scope starts after the
+    /// statement declaring it, so `local a, b = x, y` writes `a` before it
+    /// is active. Only writes in the block the scope starts in count as a
+    /// declaration; the branches of `local x = a or b` just assign it.
+    fn written_local(&self, reg: u32, pc: usize) -> Option<(&'a DebugLocal, bool)> {
+        let func = self.func;
+        let insts = &func.instructions;
+        let same_block =
+            |start: usize| start < insts.len() && self.cfg.block_of(start) == self.cfg.block_of(pc);
+        if let Some(local) = func.local_at(pc + 1, reg) {
+            let declares = local.start as usize == pc + 1 && same_block(pc + 1);
+            return Some((local, declares));
+        }
+        let start = func
+            .debug_info
+            .locals
+            .iter()
+            .map(|local| local.start as usize)
+            .filter(|&start| start > pc + 1)
+            .min()?;
+        // The value must still be in the register when the scope starts.
+        let touched = (pc + 1..start.min(insts.len())).any(|at| {
+            let access = self.def_use.access(at, None);
+            access.reads.contains(&reg) || access.writes.contains(&reg)
+        });
+        if touched {
+            return None;
+        }
+        func.local_at(start, reg)
+            .map(|local| (local, same_block(start)))
+    }
+
+    /// Assigns `values` to registers `regs` written at `pc`, declaring
+    /// them as locals if their scope starts here.
+    fn store(&mut self, pc: usize, regs: &[u32], mut values: Vec<Expr>) {
+        let mut targets: Vec<Expr> = regs.iter().map(|&reg| self.target(reg, pc)).collect();
+        let starts: Vec<Option<usize>> = regs
+            .iter()
+            .map(|&reg| match self.written_local(reg, pc) {
+                Some((local, true)) if is_identifier(&local.local_name) => {
+                    Some(local.start as usize)
+                }
+                _ => None,
+            })
+            .collect();
+        let start = match starts.first() {
+            Some(&Some(start)) if starts.iter().all(|&s| s == Some(start)) => start,
+            _ => {
+                self.body.push(Stmt::Assign(targets, values));
+                return;
+            }
+        };
+        for (&reg, target) in regs.iter().zip(&mut targets) {
+            self.declared.insert((reg, start));
+            *target = self.declaration(reg, start, target.clone());
+        }
+        // `local a, b` compiles to a LOADNIL.
+        if values == [Expr::Nil] {
+            values.clear();
+        }
+        // Further values of the same declaration, as in `local a, b = x, y`.
+        if self.declaring == Some(start) {
+            if let Some(Stmt::Local(prev_targets, prev_values)) = self.body.last_mut() {
+                if prev_targets.len() == prev_values.len() {
+                    prev_targets.extend(targets);
+                    prev_values.extend(values);
+                    return;
+                }
+            }
+        }
+        self.body.push(Stmt::Local(targets, values));
+        self.declaring = Some(start);
+    }
+
+    /// The target declaring the local in `reg` whose scope starts at
+    /// `start`, annotated with its type if it has one.
+    fn declaration(&self, reg: u32, start: usize, variable: Expr) -> Expr {
+        match self.annotations.get(&(reg, start)) {
+            Some(type_name) => Expr::Typed(Box::new(variable), type_name.clone()),
+            None => variable,
+        }
+    }
+
+    /// Emits the assignments of a `SELF` at `pc` that could not be turned
+    /// into a method call: the object is evaluated once, into `base + 1`.
+    fn store_self(&mut self, pc: usize, base: u32, object: Expr, key: Expr) {
+        let method = Expr::index(self.target(base + 1, pc), key);
+        self.store(pc, &[base + 1], vec![object]);
+        self.store(pc, &[base], vec![method]);
+    }
+
+    /// Declares the locals whose scope starts at `pc` without a write
+    /// declaring them: those of a `local function`, which is in scope in
+    /// its own body, and those the compiler leaves to the nil registers
+    /// start with.
+    fn declare_locals(&mut self, pc: usize) {
+        let func = self.func;
+        let insts = &func.instructions;
+        for reg in 0.. {
+            let Some(local) = func.local_at(pc, reg) else {
+                break;
+            };
+            if local.start as usize != pc
+                || !is_identifier(&local.local_name)
+                || self.declared.contains(&(reg, pc))
+            {
+                continue;
+            }
+            let declared = if pc == 0 {
+                // Neither the parameters nor the `arg` table of vararg functions.
+                reg >= func.param_count
+                    && (local.local_name != "arg" || matches!(func.vararg, VarArgFlags::None))
+            } else {
+                insts[pc].opcode == OpCode::CLOSURE && insts[pc].a() == reg
+            };
+            if declared {
+                self.declared.insert((reg, pc));
+                let target = self.declaration(reg, pc, self.variable(reg, pc));
+                self.body.push(Stmt::Local(vec![target], Vec::new()));
+                self.declaring = None;
+            }
+        }
+    }
+
+    fn constant(&self, index: u32) -> Expr {
+        match self.func.consts.constants.get(index as usize) {
+            Some(constant) => constant_expr(&constant.constant),
+            None => Expr::Global(format!("K{}", index)),
+        }
+    }
+
+    fn rk(&mut self, arg: OpArg) -> Expr {
+        match arg.mode {
+            OpArgMode::CONST => self.constant(arg.value),
+            _ => self.reg(arg.value),
+        }
+    }
+
+    fn upvalue(&self, index: u32) -> Expr {
+        Expr::Upvalue(upvalue_name(self.func, index))
+    }
+
+    fn global(&self, index: u32) -> Expr {
+        match self.constant(index) {
+            Expr::String(name) => Expr::Global(name),
+            other => Expr::index(Expr::Global("_G".to_string()), other),
+        }
+    }
+
+    fn assign(&mut self, target: Expr, value: Expr) {
+        self.body.push(Stmt::Assign(vec![target], vec![value]));
+    }
+
+    /// Assigns `value` to register `reg`, or keeps it pending if the
+    /// instruction being lifted computes a temporary.
+    fn set(&mut self, reg: u32, value: Expr) {
+        if self.defer {
+            self.pending.push(Pending::Value {
+                reg,
+                pc: self.pc,
+                expr: value,
+            });
+        } else {
+            self.store(self.pc, &[reg], vec![value]);
+        }
+    }
+
+    /// Collects the values in registers `from..` up to the top of the stack
+    /// left by the last multiple-results expression.
+    fn open_values(&mut self, from: u32) -> Vec<Expr> {
+        let open = self
+            .taken
+            .iter()
+            .position(|pending| matches!(pending, Pending::Open { .. }));
+        match open.map(|index| self.taken.remove(index)) {
+            Some(Pending::Open { base, expr }) => {
+                let mut values: Vec<Expr> = (from..base).map(|reg| self.reg(reg)).collect();
+                values.push(expr);
+                values
+            }
+            _ => vec![Expr::VarArg],
+        }
+    }
+
+    /// Binds the results of a multiple-result expression to registers
+    /// `base..base + count - 1`, where count 0 leaves them open.
+    fn results(&mut self, base: u32, count: u32, expr: Expr) {
+        match count {
+            0 => self.pending.push(Pending::Open { base, expr }),
+            1 => self.body.push(Stmt::Call(expr)),
+            2 => self.set(base, expr),
+            _ => {
+                let regs: Vec<u32> = (base..base + count - 1).collect();
+                self.store(self.pc, &regs, vec![expr]);
+            }
+        }
+    }
+
+    /// Builds the call made by a `CALL` or `TAILCALL` in register `a` with
+    /// operand B `b`, as a method call if a `SELF` prepared it.
+    fn call(&mut self, a: u32, b: u32) -> Expr {
+        let method = self
+            .taken
+            .iter()
+            .position(|pending| matches!(pending, Pending::Method { base, .. } if *base == a));
+        if let Some(Pending::Method { object, name, .. }) =
+            method.map(|index| self.taken.remove(index))
+        {
+            let args = match b {
+                0 => self.open_values(a + 2),
+                b => self.fixed(a + 2, b.saturating_sub(2)),
+            };
+            return Expr::MethodCall(Box::new(object), name, args);
+        }
+        let func = self.reg(a);
+        let args = match b {
+            0 => self.open_values(a + 1),
+            b => self.fixed(a + 1, b - 1),
+        };
+        Expr::Call(Box::new(func), args)
+    }
+
+    /// The condition under which the test at `pc` does not skip the
+    /// following instruction, with the temporaries it reads inlined if it
+    /// was the last test lifted.
+    pub(crate) fn condition(&mut self, pc: usize) -> Expr {
+        match self.branch.take() {
+            Some((branch, cond)) if branch == pc => cond,
+            _ => {
+                let func = self.func;
+                self.test_condition(&func.instructions[pc])
+            }
+        }
+    }
+
+    /// Whether every instruction in `start..end` computes a temporary that
+    /// is inlined into a later one, so that lifting them emits nothing.
+    pub(crate) fn only_temporaries(&self, start: usize, end: usize) -> bool {
+        (start..end).all(|pc| self.def_use.folds(pc))
+    }
+
+    /// The start, limit and step of the numeric `for` loop prepared by the
+    /// `FORPREP` at `pc`.
+    pub(crate) fn for_operands(&mut self, pc: usize) -> [Expr; 3] {
+        match self.for_prep.take() {
+            Some((prep, operands)) if prep == pc => operands,
+            _ => {
+                let a = self.func.instructions[pc].a();
+                [a, a + 1, a + 2].map(|reg| self.variable(reg, pc))
+            }
+        }
+    }
+
+    /// Builds the condition under which a test instruction does not skip
+    /// the following instruction.
+    fn test_condition(&mut self, inst: &LuaInstruction) -> Expr {
+        match inst.opcode {
+            OpCode::EQ
+            | OpCode::EQ_BK
+            | OpCode::LT
+            | OpCode::LT_BK
+            | OpCode::LE
+            | OpCode::LE_BK => {
+                let op = match inst.opcode {
+                    OpCode::EQ | OpCode::EQ_BK => BinOp::Eq,
+                    OpCode::LT | OpCode::LT_BK => BinOp::Lt,
+                    _ => BinOp::Le,
+                };
+                let lhs = self.rk(inst.b());
+                let rhs = self.rk(inst.c());
+                let cond = Expr::binary(op, lhs, rhs);
+                if inst.a() != 0 {
+                    cond
+                } else {
+                    cond.negate()
+                }
+            }
+            OpCode::TESTSET => {
+                let value = self.reg(inst.b().value);
+                if inst.c().value != 0 {
+                    value
+                } else {
+                    value.negate()
+                }
+            }
+            _ => {
+                let value = self.reg(inst.a());
+                if inst.c().value != 0 {
+                    value
+                } else {
+                    value.negate()
+                }
+            }
+        }
+    }
+
+    /// Lifts the instruction at `pc` and returns the pc of the next
+    /// instruction to lift.
+    fn lift_instruction(&mut self, pc: usize) -> usize {
+        let inst = &self.func.instructions[pc];
+        let open = self.pending.iter().rev().find_map(|pending| match pending {
+            Pending::Open { base, .. } => Some(*base),
+            _ => None,
+        });
+        self.pc = pc;
+        let access = self.def_use.access(pc, open);
+        self.defer = self.def_use.folds(pc) || opens_results(inst);
+        self.take(&access);
+        let emitted = self.body.len();
+        let next = self.lift_operation(pc);
+        // Anything taken but left unused was computed before this
+        // instruction ran.
+        let leftover = std::mem::take(&mut self.taken);
+        if !leftover.is_empty() {
+            let after = self.body.split_off(emitted);
+            for pending in leftover {
+                self.emit(pending);
+            }
+            self.body.extend(after);
+            self.declaring = None;
+        }
+        next
+    }
+
+    fn lift_operation(&mut self, pc: usize) -> usize {
+        let func = self.func;
+        let insts = &func.instructions;
+        let inst = &insts[pc];
+        let a = inst.a();
+        let next = pc + 1;
+        match inst.opcode {
+            OpCode::GETFIELD | OpCode::GETFIELD_R1 | OpCode::GETFIELD_MM => {
+                let table = self.reg(inst.b().value);
+                let value = Expr::index(table, self.constant(inst.c().value));
+                self.set(a, value);
+            }
+            OpCode::GETTABLE_S | OpCode::GETTABLE_N | OpCode::GETTABLE => {
+                let table = self.reg(inst.b().value);
+                let value = Expr::index(table, self.rk(inst.c()));
+                self.set(a, value);
+            }
+            OpCode::SETFIELD | OpCode::SETFIELD_R1 => {
+                let target = Expr::index(self.reg(a), self.constant(inst.b().value));
+                let value = self.rk(inst.c());
+                self.assign(target, value);
+            }
+            OpCode::SETTABLE_S
+            | OpCode::SETTABLE_S_BK
+            | OpCode::SETTABLE_N
+            | OpCode::SETTABLE_N_BK
+            | OpCode::SETTABLE
+            | OpCode::SETTABLE_BK => {
+                let table = self.reg(a);
+                let target = Expr::index(table, self.rk(inst.b()));
+                let value = self.rk(inst.c());
+                self.assign(target, value);
+            }
+            OpCode::MOVE => {
+                let value = self.reg(inst.b().value);
+                self.set(a, value);
+            }
+            OpCode::LOADK => {
+                let value = self.constant(inst.b().value);
+                self.set(a, value);
+            }
+            OpCode::LOADBOOL => self.set(a, Expr::Bool(inst.b().value != 0)),
+            OpCode::LOADNIL => {
+                let regs: Vec<u32> = (a..=inst.b().value).collect();
+                self.store(pc, &regs, vec![Expr::Nil]);
+            }
+            OpCode::GETGLOBAL | OpCode::GETGLOBAL_MEM => {
+                let value = self.global(inst.b().value);
+                self.set(a, value);
+            }
+            OpCode::SETGLOBAL => {
+                let target = self.global(inst.b().value);
+                let value = self.reg(a);
+                self.assign(target, value);
+            }
+            OpCode::GETUPVAL => {
+                let value = self.upvalue(inst.b().value);
+                self.set(a, value);
+            }
+            OpCode::SETUPVAL | OpCode::SETUPVAL_R1 => {
+                let target = self.upvalue(inst.b().value);
+                let value = self.reg(a);
+                self.assign(target, value);
+            }
+            OpCode::ADD
+            | OpCode::ADD_BK
+            | OpCode::SUB
+            | OpCode::SUB_BK
+            | OpCode::MUL
+            | OpCode::MUL_BK
+            | OpCode::DIV
+            | OpCode::DIV_BK
+            | OpCode::MOD
+            | OpCode::MOD_BK
+            | OpCode::POW
+            | OpCode::POW_BK => {
+                let op = match inst.opcode {
+                    OpCode::ADD | OpCode::ADD_BK => BinOp::Add,
+                    OpCode::SUB | OpCode::SUB_BK => BinOp::Sub,
+                    OpCode::MUL | OpCode::MUL_BK => BinOp::Mul,
+                    OpCode::DIV | OpCode::DIV_BK => BinOp::Div,
+                    OpCode::MOD | OpCode::MOD_BK => BinOp::Mod,
+                    _ => BinOp::Pow,
+                };
+                let lhs = self.rk(inst.b());
+                let rhs = self.rk(inst.c());
+                self.set(a, Expr::binary(op, lhs, rhs));
+            }
+            OpCode::UNM | OpCode::NOT | OpCode::NOT_R1 | OpCode::LEN => {
+                let op = match inst.opcode {
+                    OpCode::UNM => UnOp::Neg,
+                    OpCode::LEN => UnOp::Len,
+                    _ => UnOp::Not,
+                };
+                let value = Expr::unary(op, self.reg(inst.b().value));
+                self.set(a, value);
+            }
+            OpCode::CONCAT => {
+                let values: Vec<Expr> = (inst.b().value..=inst.c().value)
+                    .map(|reg| self.reg(reg))
+                    .collect();
+                // `..` is right associative, as is a single CONCAT of
+                // several values.
+                let value = values
+                    .into_iter()
+                    .rev()
+                    .reduce(|rhs, lhs| Expr::binary(BinOp::Concat, lhs, rhs))
+                    .unwrap_or(Expr::String(String::new()));
+                self.set(a, value);
+            }
+            OpCode::NEWTABLE => self.set(a, Expr::Table(vec![], vec![])),
+            OpCode::NEWSTRUCT => {
+                let name = self.context.types.struct_name(inst.b().value);
+                self.set(a, Expr::Struct(name));
+            }
+            // Extra operands for the instruction before. They are not
+            // decoded, so nothing is lifted from them.
+            OpCode::DATA => {}
+            // Checks declaring the type of a local are part of its
+            // declaration.
+            opcode if is_type_check(opcode) && checked_local(func, pc).is_some() => {}
+            OpCode::SETSLOTN => {
+                let target = Expr::index(self.reg(a), slot_key(inst.c().value));
+                self.assign(target, Expr::Nil);
+            }
+            OpCode::SETSLOTI | OpCode::SETSLOT | OpCode::SETSLOTS | OpCode::SETSLOTMT => {
+                let target = Expr::index(self.reg(a), slot_key(inst.b().value));
+                let value = self.rk(inst.c());
+                self.assign(target, value);
+            }
+            OpCode::GETSLOT | OpCode::GETSLOTMT | OpCode::GETSLOT_D => {
+                let value = Expr::index(self.reg(inst.b().value), slot_key(inst.c().value));
+                self.set(a, value);
+            }
+            OpCode::SETLIST => {
+                let values = match inst.b().value {
+                    0 => self.open_values(a + 1),
+                    count => self.fixed(a + 1, count),
+                };
+                let block = inst.c().value.max(1);
+                let first = (block - 1) * FIELDS_PER_FLUSH + 1;
+                let table = self.reg(a);
+                let targets = (0..values.len() as u32)
+                    .map(|i| Expr::index(table.clone(), Expr::Number((first + i) as f64)))
+                    .collect();
+                self.body.push(Stmt::Assign(targets, values));
+            }
+            OpCode::SELF => {
+                let object = self.reg(inst.b().value);
+                match self.rk(inst.c()) {
+                    Expr::String(name) if self.defer => {
+                        self.pending.push(Pending::Method {
+                            base: a,
+                            pc,
+                            object,
+                            name,
+                        });
+                    }
+                    key => self.store_self(pc, a, object, key),
+                }
+            }
+            OpCode::SELFSLOT | OpCode::SELFSLOTMT => {
+                let object = self.reg(inst.b().value);
+                let name = slot_name(inst.c().value);
+                if self.defer {
+                    self.pending.push(Pending::Method {
+                        base: a,
+                        pc,
+                        object,
+                        name,
+                    });
+                } else {
+                    self.store_self(pc, a, object, Expr::String(name));
+                }
+            }
+            OpCode::INTRINSIC_INDEX => {
+                let object = self.reg(inst.b().value);
+                let value = self.intrinsic_index(object, inst.c().value);
+                self.set(a, value);
+            }
+            OpCode::INTRINSIC_LITERAL => {
+                let object = self.reg(a);
+                let value = self.intrinsic_index(object, inst.b().value);
+                self.set(a, value);
+            }
+            OpCode::INTRINSIC_NEWINDEX => {
+                let object = self.reg(a);
+                let value = self.reg(inst.c().value);
+                self.intrinsic_newindex(object, inst.b().value, value);
+            }
+            OpCode::INTRINSIC_NEWINDEX_LITERAL => {
+                let object = self.reg(a);
+                let value = self.reg(a + 1);
+                self.intrinsic_newindex(object, inst.b().value, value);
+            }
+            OpCode::INTRINSIC_SELF => {
+                let object = self.reg(inst.b().value);
+                self.intrinsic_self(pc, a, object, inst.c().value);
+            }
+            OpCode::INTRINSIC_SELF_LITERAL => {
+                let object = self.reg(a);
+                self.intrinsic_self(pc, a, object, inst.b().value);
+            }
+            OpCode::CALL | OpCode::CALL_I | OpCode::CALL_C | OpCode::CALL_M | OpCode::CALL_I_R1 => {
+                let call = self.call(a, inst.b().value);
+                self.results(a, call_results(inst), call);
+            }
+            OpCode::TAILCALL
+            | OpCode::TAILCALL_I
+            | OpCode::TAILCALL_C
+            | OpCode::TAILCALL_M
+            | OpCode::TAILCALL_I_R1 => {
+                let call = self.call(a, inst.b().value);
+                self.body.push(Stmt::Return(vec![call]));
+                // A tail call is always followed by a RETURN that is never reached.
+                if insts.get(next).map(|i| i.opcode) == Some(OpCode::RETURN) {
+                    return next + 1;
+                }
+            }
+            OpCode::RETURN => {
+                let values = if inst.b().value == 0 {
+                    self.open_values(a)
+                } else {
+                    self.fixed(a, inst.b().value - 1)
+                };
+                // The compiler terminates every function with a bare return.
+                if !(values.is_empty() && next == insts.len()) {
+                    self.body.push(Stmt::Return(values));
+                }
+            }
+            OpCode::VARARG => self.results(a, inst.b().value, Expr::VarArg),
+            OpCode::EQ
+            | OpCode::EQ_BK
+            | OpCode::LT
+            | OpCode::LT_BK
+            | OpCode::LE
+            | OpCode::LE_BK
+            | OpCode::TEST
+            | OpCode::TEST_R1
+            | OpCode::TESTSET => {
+                let cond = self.test_condition(inst);
+                self.branch = Some((pc, cond));
+            }
+            OpCode::FORPREP => {
+                let operands = [a, a + 1, a + 2].map(|reg| self.reg(reg));
+                self.for_prep = Some((pc, operands));
+            }
+            OpCode::JMP | OpCode::FORLOOP | OpCode::TFORLOOP => {}
+            OpCode::CLOSE => {}
+            OpCode::CLOSURE => {
+                let index = inst.b().value as usize;
+                if let Some(child) = self.func.child_functions.get(index) {
+                    let closure = lift(child, self.context);
+                    self.set(a, Expr::Closure(Box::new(closure)));
+                    // Upvalues are captured by MOVE/GETUPVAL pseudo-instructions.
+                    let captures = child.upvalue_count as usize;
+                    let mut resume = next;
+                    while resume < next + captures
+                        && matches!(
+                            insts.get(resume).map(|i| i.opcode),
+                            Some(OpCode::MOVE) | Some(OpCode::GETUPVAL)
+                        )
+                    {
+                        resume += 1;
+                    }
+                    return resume;
+                }
+                self.unsupported(inst);
+            }
+            _ => self.unsupported(inst),
+        }
+        next
+    }
+
+    /// Reads intrinsic `id` of `object`: the field it names, or a
+    /// `__intrinsic_index` pseudo-call if it is unknown.
+    fn intrinsic_index(&self, object: Expr, id: u32) -> Expr {
+        match self.context.intrinsics.name(id) {
+            Some(name) => Expr::index(object, Expr::String(name.to_string())),
+            None => pseudo_call("__intrinsic_index", vec![object, Expr::Number(id as f64)]),
+        }
+    }
+
+    fn intrinsic_newindex(&mut self, object: Expr, id: u32, value: Expr) {
+        match self.context.intrinsics.name(id) {
+            Some(name) => {
+                let target = Expr::index(object, Expr::String(name.to_string()));
+                self.assign(target, value);
+            }
+            None => {
+                let args = vec![object, Expr::Number(id as f64), value];
+                self.body
+                    .push(Stmt::Call(pseudo_call("__intrinsic_newindex", args)));
+            }
+        }
+    }
+
+    /// Lifts an intrinsic `SELF` at `pc` loading intrinsic `id` of
+    /// `object` into `base` and `object` into `base + 1`. An unknown
+    /// intrinsic is called as the function its pseudo-call returns.
+    fn intrinsic_self(&mut self, pc: usize, base: u32, object: Expr, id: u32) {
+        let Some(name) = self.context.intrinsics.name(id) else {
+            if self.defer {
+                let method = self.intrinsic_index(object.clone(), id);
+                for (reg, expr) in [(base, method), (base + 1, object)] {
+                    self.pending.push(Pending::Value { reg, pc, expr });
+                }
+            } else {
+                let method = self.intrinsic_index(self.target(base + 1, pc), id);
+                self.store(pc, &[base + 1], vec![object]);
+                self.store(pc, &[base], vec![method]);
+            }
+            return;
+        };
+        let name = name.to_string();
+        if self.defer {
+            self.pending.push(Pending::Method {
+                base,
+                pc,
+                object,
+                name,
+            });
+        } else {
+            self.store_self(pc, base, object, Expr::String(name));
+        }
+    }
+
+    fn fixed(&mut self, from: u32, count: u32) -> Vec<Expr> {
+        (from..from + count).map(|reg| self.reg(reg)).collect()
+    }
+
+    fn unsupported(&mut self, inst: &LuaInstruction) {
+        let operands = inst
+            .args
+            .iter()
+            .map(|arg| arg.value.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.body
+            .push(Stmt::Comment(format!("{:?} {}", inst.opcode, operands)));
+    }
+}
+
+fn pseudo_call(name: &str, args: Vec<Expr>) -> Expr {
+    Expr::Call(Box::new(Expr::Global(name.to_string())), args)
+}
+
+fn slot_key(index: u32) -> Expr {
+    Expr::String(slot_name(index))
+}
+
+pub fn constant_expr(constant: &BungieConstantEnum) -> Expr {
+    match constant {
+        BungieConstantEnum::None => Expr::Nil,
+        BungieConstantEnum::Bool(b) => Expr::Bool(*b != 0),
+        BungieConstantEnum::LightUserData(v) => Expr::LightUserData(*v),
+        // Round-trip through the f32 text form so 0.1f32 prints as 0.1.
+        BungieConstantEnum::Number(LuaNumber::F32(n)) => {
+            Expr::Number(n.to_string().parse().unwrap_or(*n as f64))
+        }
+        BungieConstantEnum::Number(n) => Expr::Number(n.as_f64()),
+        BungieConstantEnum::String(s) => Expr::String(s.const_string.clone()),
+        BungieConstantEnum::U64(v) => Expr::UI64(*v),
+    }
+}
@@ -0,0 +1,270 @@
+use crate::ast::{quote_string, LuaWriter};
+use crate::decompiler::{constant_expr, upvalue_name};
+use crate::opcodes::*;
+use crate::structs::*;
+use crate::types::TypeRegistry;
+use std::fmt::Write;
+
+/// Produces a `luac -l` style listing of `func` and every prototype nested
+/// in it. The listing is valid [`crate::asm`] source for the prototypes.
+pub fn disassemble(func: &FunctionBlock) -> String {
+    let mut out = String::new();
+    write_function(&mut out, func, &mut vec![]);
+    out
+}
+
+/// Like [`disassemble`], but also lists the header and the file-level type
+/// constants so the output assembles back into the same chunk. Each type
+/// constant is commented with the kind of type it registers.
+///
+/// The bytes come back unchanged only for canonical chunks, laid out the
+/// way the compiler and the assembler write them. The listing does not
+/// record string sizes, padding or bytes after the main function, so any
+/// other chunk assembles into its canonical form, and the addresses in the
+/// comments move accordingly.
+pub fn disassemble_chunk(chunk: &Chunk) -> String {
+    let header = &chunk.header;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        ".header version=0x{:X} format=0x{:X} endian={} int_size={} size_t={} \
+         instruction_size={} number_size={} number_type={} integral_flag={} unk={}",
+        header.version,
+        header.format,
+        match header.endianness {
+            LuaEndian::Big => "big",
+            LuaEndian::Little => "little",
+        },
+        header.int_size,
+        header.size_t,
+        header.instruction_size,
+        header.number_size,
+        match header.number_type {
+            LuaNumberType::Float => "float",
+            LuaNumberType::Integer => "integer",
+        },
+        header.integral_flag,
+        header.unk
+    );
+    let types = TypeRegistry::new(&chunk.type_constants);
+    for constant in &chunk.type_constants.constants {
+        let _ = write!(
+            out,
+            ".type {} {}",
+            constant.constant_type,
+            quote_string(&constant.const_string)
+        );
+        match types.get(constant.constant_type) {
+            Some(entry) if entry.name == constant.const_string => {
+                let _ = writeln!(out, " ; {}", entry.kind.as_str());
+            }
+            _ => out.push_str(" ; shadowed\n"),
+        }
+    }
+    out.push('\n');
+    write_function(&mut out, &chunk.main_function, &mut vec![]);
+    out
+}
+
+/// Writes one prototype; `path` holds the child indices leading to it and
+/// is used to name nested functions (`main`, `main.0`, `main.0.2`, ...).
+fn write_function(out: &mut String, func: &FunctionBlock, path: &mut Vec<usize>) {
+    let name = prototype_name(path);
+    let _ = write!(out, ".function {} ;", name);
+    if func.has_debug_info {
+        let debug = &func.debug_info;
+        // Quoted like string constants, so that neither can end the
+        // comment line early.
+        if !debug.function_name.is_empty() {
+            let _ = write!(out, " {}", quote_string(&debug.function_name));
+        }
+        let _ = write!(
+            out,
+            " <{}:{},{}>",
+            quote_string(&debug.path),
+            debug.line_begin,
+            debug.line_end
+        );
+    }
+    let _ = writeln!(
+        out,
+        " ({} instructions at 0x{:X})",
+        func.instructions.len(),
+        func.address
+    );
+    let _ = writeln!(
+        out,
+        "; {}{} params, {} upvalues, {} constants, {} functions",
+        func.param_count,
+        if func.is_vararg() { "+" } else { "" },
+        func.upvalue_count,
+        func.consts.constants.len(),
+        func.child_functions.len()
+    );
+
+    if func.depth > 0 {
+        let _ = writeln!(out, ".unk0 {}", func.unk0);
+    }
+    let _ = writeln!(out, ".upvalues {}", func.upvalue_count);
+    let _ = writeln!(out, ".params {}", func.param_count);
+    let _ = writeln!(out, ".vararg {}", func.vararg as u8);
+    if func.depth == 0 {
+        let _ = writeln!(out, ".slots {}", func.unk9);
+    }
+    for (index, constant) in func.consts.constants.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            ".const {} ; K({})",
+            constant_source(&constant.constant),
+            index
+        );
+    }
+    if func.has_debug_info {
+        let debug = &func.debug_info;
+        let _ = writeln!(
+            out,
+            ".debug {} {} {} {}",
+            quote_string(&debug.path),
+            quote_string(&debug.function_name),
+            debug.line_begin,
+            debug.line_end
+        );
+        for local in &debug.locals {
+            let _ = writeln!(
+                out,
+                ".local {} {} {}",
+                quote_string(&local.local_name),
+                local.start,
+                local.end
+            );
+        }
+        for upvalue in &debug.upvalues {
+            let _ = writeln!(out, ".upvalue {}", quote_string(&upvalue.string));
+        }
+    }
+
+    for pc in 0..func.instructions.len() {
+        let _ = writeln!(out, "{}", instruction_line(func, pc));
+    }
+    out.push('\n');
+
+    for (index, child) in func.child_functions.iter().enumerate() {
+        path.push(index);
+        write_function(out, child, path);
+        path.pop();
+    }
+    let _ = writeln!(out, ".end ; {}", name);
+}
+
+/// A constant as the assembler reads it back, keeping the exact value of
+/// the stored number rather than its Lua rendering.
+pub(crate) fn constant_source(constant: &BungieConstantEnum) -> String {
+    match constant {
+        BungieConstantEnum::None => "nil".to_string(),
+        BungieConstantEnum::Bool(b) => (*b != 0).to_string(),
+        BungieConstantEnum::LightUserData(v) => format!("lightuserdata 0x{:X}", v),
+        BungieConstantEnum::Number(LuaNumber::F32(n)) => format!("{:?}", n),
+        BungieConstantEnum::Number(LuaNumber::F64(n)) => format!("{:?}", n),
+        BungieConstantEnum::Number(LuaNumber::I32(n)) => n.to_string(),
+        BungieConstantEnum::Number(LuaNumber::I64(n)) => n.to_string(),
+        BungieConstantEnum::String(s) => quote_string(&s.const_string),
+        BungieConstantEnum::U64(v) => format!("0x{:X}hk", v),
+    }
+}
+
+/// Names a prototype by the child indices leading to it from the main
+/// chunk: `main`, `main.0`, `main.0.2`, ...
+pub fn prototype_name(path: &[usize]) -> String {
+    let mut name = String::from("main");
+    for index in path {
+        let _ = write!(name, ".{}", index);
+    }
+    name
+}
+
+/// Formats a single instruction as `pc [line] OPCODE operands ; comment`.
+pub fn instruction_line(func: &FunctionBlock, pc: usize) -> String {
+    let inst = &func.instructions[pc];
+    let line = match func.debug_info.lines.get(pc) {
+        Some(line) if func.has_debug_info => line.to_string(),
+        _ => "-".to_string(),
+    };
+
+    let operands = operands(inst);
+    let comments = comments(func, pc);
+    let mut text = format!(
+        "\t{}\t[{}]\t{:<26} {:<16}",
+        pc,
+        line,
+        format!("{:?}", inst.opcode),
+        operands
+    );
+    if !comments.is_empty() {
+        let _ = write!(text, "; {}", comments.join(", "));
+    }
+    text.trim_end().to_string()
+}
+
+fn operands(inst: &LuaInstruction) -> String {
+    let mut operands = Vec::with_capacity(inst.args.len());
+    for (i, arg) in inst.args.iter().enumerate() {
+        let is_offset = i == 1 && inst.opmodes.mode == OpMode::iAsBx;
+        let text = match arg.mode {
+            OpArgMode::CONST => format!("K({})", arg.value),
+            _ if is_offset => inst.sbx().to_string(),
+            _ => arg.value.to_string(),
+        };
+        operands.push(text);
+    }
+    operands.join(" ")
+}
+
+fn comments(func: &FunctionBlock, pc: usize) -> Vec<String> {
+    let inst = &func.instructions[pc];
+    let mut comments = Vec::new();
+
+    for arg in inst.args.iter().filter(|arg| arg.mode == OpArgMode::CONST) {
+        let value = match func.consts.constants.get(arg.value as usize) {
+            Some(constant) => LuaWriter::new().expr(&constant_expr(&constant.constant)),
+            None => "<out of range>".to_string(),
+        };
+        comments.push(format!("K({}) = {}", arg.value, value));
+    }
+
+    // Registers holding a local, which for A may only be declared by this
+    // instruction. A bare RETURN reads none.
+    let mut named = Vec::new();
+    if inst.opcode == OpCode::RETURN && inst.b().value == 1 {
+        named.push(inst.a());
+    }
+    for (index, arg) in inst.args.iter().enumerate() {
+        if arg.mode != OpArgMode::REG || named.contains(&arg.value) {
+            continue;
+        }
+        let local = func
+            .local_at(pc, arg.value)
+            .or_else(|| func.local_at(pc + 1, arg.value).filter(|_| index == 0));
+        if let Some(local) = local {
+            comments.push(format!("R({}) = {}", arg.value, local.local_name));
+            named.push(arg.value);
+        }
+    }
+
+    match inst.opcode {
+        OpCode::GETUPVAL | OpCode::SETUPVAL | OpCode::SETUPVAL_R1 => {
+            let index = inst.b().value;
+            comments.push(format!("U({}) = {}", index, upvalue_name(func, index)));
+        }
+        OpCode::JMP | OpCode::FORPREP | OpCode::FORLOOP => {
+            let target = pc as i64 + 1 + inst.sbx() as i64;
+            comments.push(format!("to {}", target));
+        }
+        OpCode::LOADBOOL if inst.c().value != 0 => comments.push(format!("to {}", pc + 2)),
+        OpCode::CLOSURE => match func.child_functions.get(inst.b().value as usize) {
+            Some(child) => comments.push(format!("function at 0x{:X}", child.address)),
+            None => comments.push("<missing function>".to_string()),
+        },
+        _ => {}
+    }
+    comments
+}
@@ -0,0 +1,131 @@
+use crate::cfg::{Cfg, EdgeKind};
+use crate::disasm::{instruction_line, prototype_name};
+use crate::opcodes::OpCode;
+use crate::structs::*;
+use crate::types::TypeRegistry;
+use std::fmt::Write;
+
+/// Renders the control-flow graphs of the prototypes of `chunk` as a
+/// single Graphviz DOT graph, one cluster per prototype, headed by a
+/// comment per type the chunk registers. Nodes are basic blocks listing
+/// their instructions; a dashed edge leads from each `CLOSURE` to the
+/// entry of the prototype it creates.
+///
+/// The edges out of a test are labelled `taken` where the test does not
+/// skip the next instruction, usually a jump, and `not taken` where it
+/// does. They do not say whether the condition is true: `TEST` and
+/// `TESTSET` with C=0 branch when their register is false.
+pub fn dot(chunk: &Chunk) -> String {
+    let mut out = String::from("digraph chunk {\n");
+    for entry in TypeRegistry::new(&chunk.type_constants).iter() {
+        let _ = writeln!(out, "  // type {}", entry);
+    }
+    out.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+    write_cluster(&mut out, &chunk.main_function, &mut vec![]);
+    out.push_str("}\n");
+    out
+}
+
+fn node_id(path: &[usize], block: usize) -> String {
+    let mut id = prototype_name(path).replace('.', "_");
+    let _ = write!(id, "_b{}", block);
+    id
+}
+
+/// Escapes text for a DOT label, left-justifying every line.
+fn label_text(line: &str) -> String {
+    let mut text = String::new();
+    for c in line.chars() {
+        match c {
+            '"' => text.push_str("\\\""),
+            '\\' => text.push_str("\\\\"),
+            '\t' => text.push(' '),
+            c => text.push(c),
+        }
+    }
+    text.push_str("\\l");
+    text
+}
+
+fn write_cluster(out: &mut String, func: &FunctionBlock, path: &mut Vec<usize>) {
+    let cfg = Cfg::new(func);
+    let name = prototype_name(path);
+    let mut title = name.clone();
+    if func.has_debug_info && !func.debug_info.function_name.is_empty() {
+        let _ = write!(title, " {}", func.debug_info.function_name);
+    }
+    let _ = writeln!(out, "  subgraph cluster_{} {{", name.replace('.', "_"));
+    let _ = writeln!(
+        out,
+        "    label=\"{}\";",
+        label_text(&title).trim_end_matches("\\l")
+    );
+
+    for (index, block) in cfg.blocks.iter().enumerate() {
+        let mut label = format!("B{}\\l", index);
+        for pc in block.start..block.end {
+            label.push_str(&label_text(instruction_line(func, pc).trim()));
+        }
+        let style = if cfg.is_reachable(index) {
+            ""
+        } else {
+            ", style=dashed"
+        };
+        let _ = writeln!(
+            out,
+            "    {} [label=\"{}\"{}];",
+            node_id(path, index),
+            label,
+            style
+        );
+    }
+
+    for (index, block) in cfg.blocks.iter().enumerate() {
+        for edge in &block.successors {
+            let (label, color) = if cfg.is_back_edge(index, edge.target) {
+                ("loop-back", "blue")
+            } else {
+                match edge.kind {
+                    EdgeKind::True => ("taken", "darkgreen"),
+                    EdgeKind::False => ("not taken", "red"),
+                    EdgeKind::Jump => ("jump", "black"),
+                    EdgeKind::Fallthrough => ("fallthrough", "gray40"),
+                }
+            };
+            let _ = writeln!(
+                out,
+                "    {} -> {} [label=\"{}\", color={}];",
+                node_id(path, index),
+                node_id(path, edge.target),
+                label,
+                color
+            );
+        }
+    }
+    out.push_str("  }\n");
+
+    for (index, child) in func.child_functions.iter().enumerate() {
+        path.push(index);
+        write_cluster(out, child, path);
+        path.pop();
+    }
+
+    for (pc, inst) in func.instructions.iter().enumerate() {
+        let child = inst.b().value as usize;
+        if inst.opcode != OpCode::CLOSURE || child >= func.child_functions.len() {
+            continue;
+        }
+        if func.child_functions[child].instructions.is_empty() {
+            continue;
+        }
+        let source = node_id(path, cfg.block_of(pc));
+        path.push(child);
+        let _ = writeln!(
+            out,
+            "  {} -> {} [style=dashed, label=\"closure\"];",
+            source,
+            node_id(path, 0)
+        );
+        path.pop();
+    }
+}
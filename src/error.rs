@@ -0,0 +1,136 @@
+use std::fmt;
+
+/// Errors produced while loading, assembling or writing a bytecode file. Every variant that comes
+/// from malformed data carries the byte offset it was detected at.
+#[derive(Debug)]
+pub enum Error {
+    /// The file does not start with `\x1bLua`.
+    BadMagic {
+        offset: u64,
+    },
+    UnsupportedVersion(u8),
+    UnsupportedFormat(u8),
+    /// The header declares a primitive size the decoder cannot read.
+    UnsupportedLayout {
+        field: &'static str,
+        size: u8,
+    },
+    /// The data ended before `section` was complete, while reading at
+    /// `offset`.
+    Truncated {
+        section: &'static str,
+        offset: u64,
+    },
+    UnknownOpCode {
+        opcode: u8,
+        pc: usize,
+        offset: u64,
+    },
+    BadConstantType {
+        constant_type: u8,
+        offset: u64,
+    },
+    /// Any other malformed data in `section`.
+    Parse {
+        section: &'static str,
+        offset: u64,
+        source: binrw::Error,
+    },
+    /// A chunk could not be serialized.
+    Write {
+        section: &'static str,
+        source: binrw::Error,
+    },
+    /// Invalid assembly source, `line` is 1-based.
+    Assemble {
+        line: usize,
+        message: String,
+    },
+}
+
+impl Error {
+    /// Whether the data is a well-formed chunk of a Lua version, format or
+    /// layout this crate does not read, rather than a malformed one.
+    pub fn is_unsupported(&self) -> bool {
+        matches!(
+            self,
+            Error::UnsupportedVersion(_)
+                | Error::UnsupportedFormat(_)
+                | Error::UnsupportedLayout { .. }
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadMagic { offset } => {
+                write!(f, "bad magic at offset 0x{:X}, expected \"\\x1bLua\"", offset)
+            }
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported Lua version 0x{:X}, no known Bungie or Encounter Script uses anything but Lua 5.1",
+                version
+            ),
+            Error::UnsupportedFormat(format) => write!(
+                f,
+                "unsupported bytecode format 0x{:X}, no known Bungie or Encounter Script uses anything but format 0xE",
+                format
+            ),
+            Error::UnsupportedLayout { field, size } => {
+                write!(f, "unsupported {} of {} bytes", field, size)
+            }
+            Error::Truncated { section, offset } => {
+                write!(f, "truncated {}: data ends at offset 0x{:X}", section, offset)
+            }
+            Error::UnknownOpCode { opcode, pc, offset } => write!(
+                f,
+                "unknown opcode {} at pc {} (offset 0x{:X})",
+                opcode, pc, offset
+            ),
+            Error::BadConstantType {
+                constant_type,
+                offset,
+            } => write!(
+                f,
+                "bad constant type {} at offset 0x{:X}",
+                constant_type, offset
+            ),
+            Error::Parse {
+                section,
+                offset,
+                source,
+            } => write!(
+                f,
+                "malformed {} at offset 0x{:X}: {}",
+                section,
+                offset,
+                source.root_cause()
+            ),
+            Error::Write { section, source } => {
+                write!(f, "failed to write {}: {}", section, source.root_cause())
+            }
+            Error::Assemble { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Parse { source, .. } | Error::Write { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Raised from inside the binrw structures when a constant has a type tag
+/// the decoder does not know.
+#[derive(Debug, Clone, Copy)]
+pub struct ConstantTypeError(pub u8);
+
+impl fmt::Display for ConstantTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bad constant type {}", self.0)
+    }
+}
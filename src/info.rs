@@ -0,0 +1,188 @@
+//! Summaries of a chunk: its header, the shape of every prototype and the
+//! constants they use.
+
+use crate::disasm::{constant_source, prototype_name};
+use crate::structs::*;
+use crate::types::TypeRegistry;
+use std::fmt::Write;
+
+/// Describes the header of `chunk` and lists the types it registers and
+/// its prototypes with their sizes, debug names and source ranges.
+/// `verbose` adds each prototype's locals and upvalues.
+pub fn summary(chunk: &Chunk, verbose: bool) -> String {
+    let header = &chunk.header;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "Lua {}.{}, format {}, {} endian",
+        header.version >> 4,
+        header.version & 0xF,
+        header.format,
+        match header.endianness {
+            LuaEndian::Big => "big",
+            LuaEndian::Little => "little",
+        }
+    );
+    let _ = writeln!(
+        out,
+        "int {} bytes, size_t {} bytes, instruction {} bytes, {} {} bytes",
+        header.int_size,
+        header.size_t,
+        header.instruction_size,
+        match header.number_type {
+            LuaNumberType::Float => "float",
+            LuaNumberType::Integer => "integer",
+        },
+        header.number_size
+    );
+    let types = TypeRegistry::new(&chunk.type_constants);
+    let _ = writeln!(out, "{} types", types.len());
+    for entry in types.iter() {
+        let _ = writeln!(out, "  {}", entry);
+    }
+    let mut count = 0;
+    each_prototype(&chunk.main_function, &mut vec![], &mut |func, path| {
+        count += 1;
+        write_prototype(&mut out, func, path, verbose);
+    });
+    let _ = writeln!(out, "{} prototypes", count);
+    out
+}
+
+fn write_prototype(out: &mut String, func: &FunctionBlock, path: &[usize], verbose: bool) {
+    let _ = write!(out, "{}", prototype_name(path));
+    let debug = &func.debug_info;
+    if func.has_debug_info {
+        if !debug.function_name.is_empty() {
+            let _ = write!(out, " {}", debug.function_name);
+        }
+        let _ = write!(
+            out,
+            " <{}:{},{}>",
+            debug.path, debug.line_begin, debug.line_end
+        );
+    }
+    let _ = writeln!(
+        out,
+        ": {}{} params, {} upvalues, {} constants, {} instructions, {} functions",
+        func.param_count,
+        if func.is_vararg() { "+" } else { "" },
+        func.upvalue_count,
+        func.consts.constants.len(),
+        func.instructions.len(),
+        func.child_functions.len()
+    );
+    if verbose && func.has_debug_info {
+        for local in &debug.locals {
+            let _ = writeln!(
+                out,
+                "  local {} {}..{}",
+                local.local_name, local.start, local.end
+            );
+        }
+        for upvalue in &debug.upvalues {
+            let _ = writeln!(out, "  upvalue {}", upvalue.string);
+        }
+    }
+}
+
+/// Formats [`constants`] can list the constants in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstantsFormat {
+    /// `prototype K(index) type value`, aligned for reading.
+    Text,
+    /// `prototype,index,type,value` with a header row, strings unquoted.
+    Csv,
+}
+
+/// Lists the types `chunk` registers, as prototype `types` with the type
+/// id as index and its kind as type, then the constants of every
+/// prototype, values written as the assembler reads them.
+pub fn constants(chunk: &Chunk, format: ConstantsFormat) -> String {
+    let mut out = String::new();
+    if format == ConstantsFormat::Csv {
+        out.push_str("prototype,index,type,value\n");
+    }
+    for entry in TypeRegistry::new(&chunk.type_constants).iter() {
+        let kind = entry.kind.as_str();
+        let _ = match format {
+            ConstantsFormat::Text => writeln!(
+                out,
+                "{:<12} {:<8} {:<13} {}",
+                "types",
+                format!("T({})", entry.id),
+                kind,
+                entry.name
+            ),
+            ConstantsFormat::Csv => writeln!(
+                out,
+                "types,{},{},{}",
+                entry.id,
+                kind,
+                csv_field(&entry.name)
+            ),
+        };
+    }
+    each_prototype(&chunk.main_function, &mut vec![], &mut |func, path| {
+        let name = prototype_name(path);
+        for (index, constant) in func.consts.constants.iter().enumerate() {
+            let kind = constant_type(&constant.constant);
+            let value = constant_source(&constant.constant);
+            let _ = match format {
+                ConstantsFormat::Text => writeln!(
+                    out,
+                    "{:<12} {:<8} {:<13} {}",
+                    name,
+                    format!("K({})", index),
+                    kind,
+                    value
+                ),
+                // The type column tells strings apart, so they are written
+                // raw rather than as Lua literals.
+                ConstantsFormat::Csv => {
+                    let value = match &constant.constant {
+                        BungieConstantEnum::String(s) => s.const_string.clone(),
+                        _ => value,
+                    };
+                    writeln!(out, "{},{},{},{}", name, index, kind, csv_field(&value))
+                }
+            };
+        }
+    });
+    out
+}
+
+fn constant_type(constant: &BungieConstantEnum) -> &'static str {
+    match constant {
+        BungieConstantEnum::None => "nil",
+        BungieConstantEnum::Bool(_) => "boolean",
+        BungieConstantEnum::LightUserData(_) => "lightuserdata",
+        BungieConstantEnum::Number(_) => "number",
+        BungieConstantEnum::String(_) => "string",
+        BungieConstantEnum::U64(_) => "ui64",
+    }
+}
+
+/// Quotes a CSV field if it holds a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Calls `visit` on `func` and every prototype nested in it, depth-first,
+/// with the child indices leading to each.
+fn each_prototype(
+    func: &FunctionBlock,
+    path: &mut Vec<usize>,
+    visit: &mut dyn FnMut(&FunctionBlock, &[usize]),
+) {
+    visit(func, path);
+    for (index, child) in func.child_functions.iter().enumerate() {
+        path.push(index);
+        each_prototype(child, path, visit);
+        path.pop();
+    }
+}
@@ -0,0 +1,57 @@
+//! Names of the engine intrinsics called by the `INTRINSIC_*` opcodes.
+//!
+//! Intrinsics are fast paths the engine provides for fields and methods of
+//! its own objects, referred to by an id that differs between game builds,
+//! so their names are read from a table rather than built in. The table is
+//! a JSON object mapping ids to names:
+//!
+//! ```json
+//! { "3": "position", "7": "GetHealth" }
+//! ```
+//!
+//! The opcodes are decoded as follows, where `<id>` is the intrinsic named
+//! as a field of the object:
+//!
+//! - `INTRINSIC_INDEX A B C`: `R(A) := R(B).<C>`
+//! - `INTRINSIC_NEWINDEX A B C`: `R(A).<B> := R(C)`
+//! - `INTRINSIC_SELF A B C`: `R(A+1) := R(B); R(A) := R(B).<C>`
+//! - `INTRINSIC_LITERAL A Bx`: `R(A) := R(A).<Bx>`
+//! - `INTRINSIC_NEWINDEX_LITERAL A Bx`: `R(A).<Bx> := R(A+1)`
+//! - `INTRINSIC_SELF_LITERAL A Bx`: `R(A+1) := R(A); R(A) := R(A).<Bx>`
+//!
+//! An id missing from the table is written as a call to
+//! `__intrinsic_index(object, id)` or `__intrinsic_newindex(object, id,
+//! value)`.
+
+use crate::ast::is_identifier;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Intrinsic names by id.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Intrinsics {
+    names: BTreeMap<u32, String>,
+}
+
+impl Intrinsics {
+    /// Reads a table in the JSON form described in the module docs. Every
+    /// name must be a Lua identifier.
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let intrinsics: Intrinsics = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        for (id, name) in &intrinsics.names {
+            if !is_identifier(name) {
+                return Err(format!(
+                    "intrinsic {}: {:?} is not a Lua identifier",
+                    id, name
+                ));
+            }
+        }
+        Ok(intrinsics)
+    }
+
+    /// The name of intrinsic `id`, if the table has it.
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+}
@@ -0,0 +1,65 @@
+//! JSON export of a parsed chunk, for tools outside Rust.
+//!
+//! The document is an object with these members:
+//!
+//! - `schema_version`: [`SCHEMA_VERSION`]. It is raised, together with the
+//!   crate version, whenever a member is removed, renamed or changes
+//!   meaning; new members may be added without raising it.
+//! - `header`: `version` (0x51 for Lua 5.1), `format`, `endianness`
+//!   (`"big"` or `"little"`), `int_size`, `size_t`, `instruction_size`,
+//!   `number_size`, `number_type` (`"float"` or `"integer"`),
+//!   `integral_flag` and `unk`.
+//! - `type_constants`: an array of `{ "type", "name" }`, as in the file.
+//! - `types`: the type registry built from them, in id order, as
+//!   `{ "id", "name", "kind" }` with `kind` `"builtin"` or `"engine"`.
+//! - `main_function`: the main chunk's prototype.
+//!
+//! A prototype has `address` (its file offset), `depth` (0 for the main
+//! chunk), `unk0`, `upvalue_count`, `param_count`, `vararg` (the raw flag
+//! byte), `slots`, `instructions`, `constants`, `has_debug_info`,
+//! `debug_info` and `children`, its nested prototypes in `CLOSURE` order.
+//!
+//! An instruction has `raw`, the 32-bit word, `opcode`, its name as in
+//! the disassembly, `mode` (`"iABC"`, `"iABx"` or `"iAsBx"`) and `args`,
+//! the operands A, B and C that the opcode uses as `{ "mode", "value" }`,
+//! where `mode` is `"reg"`, `"const"` or `"number"`. The sBx operand is
+//! signed.
+//!
+//! A constant is `{ "type", "value" }` with `type` one of `"none"` (no
+//! value), `"bool"`, `"light_user_data"`, `"number"`, `"string"` or
+//! `"u64"`. Non-finite numbers are written as `null`, and 64-bit integers
+//! beyond 2^53 lose precision in readers that parse numbers as doubles.
+//!
+//! The debug info has `line_begin`, `line_end`, `path`, `function_name`,
+//! `lines` (one per instruction), `locals` as `{ "name", "start", "end" }`
+//! instruction ranges and `upvalues` as an array of names. Without debug
+//! info every member is empty or zero.
+
+use crate::structs::Chunk;
+use crate::types::{TypeEntry, TypeRegistry};
+use serde::Serialize;
+
+/// The version of the document layout described in the module docs.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Document<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    chunk: &'a Chunk,
+    types: Vec<&'a TypeEntry>,
+}
+
+/// Serializes `chunk` as a pretty-printed JSON document.
+pub fn to_json(chunk: &Chunk) -> String {
+    let types = TypeRegistry::new(&chunk.type_constants);
+    let document = Document {
+        schema_version: SCHEMA_VERSION,
+        chunk,
+        types: types.iter().collect(),
+    };
+    let mut json = serde_json::to_string_pretty(&document)
+        .expect("a chunk always serializes, having only string keys");
+    json.push('\n');
+    json
+}
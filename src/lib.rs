@@ -0,0 +1,26 @@
+//! Parser and decompiler for the Havok Script (Lua 5.1, format 14) bytecode
+//! used by Bungie's scripts in the Destiny 1 Alpha.
+
+pub mod asm;
+pub mod ast;
+pub mod batch;
+pub mod cfg;
+mod dataflow;
+pub mod decompiler;
+pub mod disasm;
+pub mod dot;
+pub mod error;
+pub mod info;
+pub mod intrinsics;
+pub mod json;
+pub mod opcodes;
+pub mod output;
+pub mod parser;
+pub mod structs;
+mod structure;
+pub mod types;
+pub mod writer;
+
+pub use error::Error;
+pub use parser::load_chunk;
+pub use structs::Chunk;
@@ -1,183 +1,359 @@
-mod opcodes;
-mod structs;
+use bungie_lua_decompiler::asm::assemble;
+use bungie_lua_decompiler::batch::{decompile_files, find_chunks, Outcome};
+use bungie_lua_decompiler::cfg::cfg_listing;
+use bungie_lua_decompiler::decompiler::decompile_chunk;
+use bungie_lua_decompiler::disasm::disassemble_chunk;
+use bungie_lua_decompiler::dot::dot;
+use bungie_lua_decompiler::info::{constants, summary, ConstantsFormat};
+use bungie_lua_decompiler::intrinsics::Intrinsics;
+use bungie_lua_decompiler::json::to_json;
+use bungie_lua_decompiler::{load_chunk, Chunk};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
 
-use crate::opcodes::*;
-use crate::structs::*;
-use binrw::BinReaderExt;
-use std::fs::File;
-use std::io::{Cursor, Read};
-use std::path::Path;
+/// Decompiler for the Havok Script (Lua 5.1, format 14) bytecode used by
+/// Bungie's scripts in the Destiny 1 Alpha.
+///
+/// Exit codes: 1 when some files of a batch failed, 2 for usage errors or
+/// an invalid intrinsic table, 3 when a file cannot be read or written, 4
+/// for malformed bytecode, 5 for bytecode of an unsupported version,
+/// format or layout and 6 for invalid assembly.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Print more detail
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    #[command(subcommand)]
+    command: Command,
+}
 
-fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
-    let mut input: &Path = Path::new("");
-    if args.len() < 2 {
-        println!("No input file specified!");
-        println!("Usage: {} <input file>", args[0]);
-        return;
-    }
-    if args.len() > 1 {
-        println!("Reading from file {}", args[1]);
-        input = Path::new(&args[1]);
-    }
-    let mut file = File::open(input).unwrap();
-    let mut file_data = Vec::new();
-    file.read_to_end(&mut file_data).unwrap();
-    let mut reader = Cursor::new(file_data);
-    let header = parse_lua_header(&mut reader);
-    println!("Header: {:#?}", header);
-    if header.version != 0x51 {
-        println!("No known Bungie or Encounter Script that uses Lua 5.1");
-        return;
-    }
-    if header.format != 0xE {
-        println!("No known Bungie or Encounter Script that does not have format 0xE");
-        return;
-    }
+#[derive(Subcommand)]
+enum Command {
+    /// Summarize the header and every function prototype
+    ///
+    /// With `-f json`, every parsed structure is written as a JSON document
+    /// instead; its schema is described in the `json` module of the crate.
+    Info {
+        #[command(flatten)]
+        io: Io,
+        #[arg(short, long, value_enum, default_value_t = InfoFormat::Text)]
+        format: InfoFormat,
+    },
+    /// Print a disassembly listing, which `assemble` reads back
+    Disasm(Io),
+    /// Decompile bytecode files, or directories searched for them, to Lua
+    ///
+    /// A single file is printed or written to the output file. Several
+    /// files, directories, or an output ending in a separator are decompiled
+    /// in parallel to their original script paths under the output
+    /// directory, which is created if missing, or next to each input as
+    /// `<name>.dec.lua` without one, and summarized as ok, partial or
+    /// failed. No input is ever written over.
+    Decompile {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Output file, or directory for several inputs or one ending in a
+        /// separator
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// JSON table naming the intrinsics of the game build by id
+        #[arg(long)]
+        intrinsics: Option<PathBuf>,
+    },
+    /// List the registered types and the constants of every prototype
+    DumpConstants {
+        #[command(flatten)]
+        io: Io,
+        #[arg(short, long, value_enum, default_value_t = ConstantsArg::Text)]
+        format: ConstantsArg,
+    },
+    /// Print the control-flow graph of every prototype
+    Cfg {
+        #[command(flatten)]
+        io: Io,
+        #[arg(short, long, value_enum, default_value_t = CfgFormat::Text)]
+        format: CfgFormat,
+    },
+    /// Assemble a listing into a bytecode file
+    Assemble {
+        listing: PathBuf,
+        /// The bytecode file to write
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
 
-    let mut section = parse_lua_section(&mut reader, LuaSectionType::TypeConstants);
-    println!("Section: {:#?}", section);
+#[derive(Args)]
+struct Io {
+    /// The bytecode file to read
+    input: PathBuf,
+    /// Write to this file instead of standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
 
-    // println!("reader pos: {}", reader.position());
-    section = parse_lua_section(&mut reader, LuaSectionType::FunctionBlock);
-    // println!("reader pos: {}", reader.position());
-    section = parse_instructions(section);
-    println!("Section: {:#?}", section);
+#[derive(Clone, Copy, ValueEnum)]
+enum InfoFormat {
+    /// A readable summary
+    Text,
+    /// The whole parsed chunk
+    Json,
+}
 
-    // println!("reader pos: {}", reader.position());
+#[derive(Clone, Copy, ValueEnum)]
+enum ConstantsArg {
+    Text,
+    Csv,
 }
 
-fn parse_instructions(section: LuaSection) -> LuaSection {
-    let mut unk1sec = match section {
-        LuaSection::FunctionBlock(s) => s,
-        _ => panic!("Expected FunctionBlock"),
-    };
-    let instructions: Vec<LuaInstruction> = unk1sec.instructions;
+#[derive(Clone, Copy, ValueEnum)]
+enum CfgFormat {
+    /// Blocks, edges and dominators
+    Text,
+    /// Graphviz DOT, one cluster per function
+    Dot,
+}
 
-    let mut new_instructions: Vec<LuaInstruction> = Vec::new();
+/// Why a command failed, and the exit code telling so.
+struct Failure {
+    code: i32,
+    message: String,
+}
 
-    for mut instruction in instructions.clone() {
-        instruction.opcode = OpCode::from((instruction.raw >> 25) as u8);
-        instruction.opmodes = OP_MODES[instruction.opcode as usize];
-        let opmodes = instruction.opmodes;
-        let mode = match instruction.opmodes.arg_mode_a {
-            OpArgModeA::UNUSED => OpArgMode::NUMBER,
-            OpArgModeA::REG => OpArgMode::REG,
-        };
-        let value = instruction.raw & 0xff;
-        instruction.args.push(OpArg { mode, value });
-
-        if opmodes.mode == OpMode::iABC {
-            if opmodes.arg_mode_b != OpArgModeBC::UNUSED {
-                let mut mode: OpArgMode = OpArgMode::NUMBER;
-                let mut value: u32 = 0;
-                match opmodes.arg_mode_b {
-                    OpArgModeBC::NUMBER => {
-                        mode = OpArgMode::NUMBER;
-                        value = instruction.raw >> 17 & 0xff;
-                    }
-                    OpArgModeBC::OFFSET => {
-                        mode = OpArgMode::NUMBER;
-                        value = instruction.raw >> 17 & 0x1ff;
-                    }
-                    OpArgModeBC::REG => {
-                        mode = OpArgMode::REG;
-                        value = (instruction.raw >> 17) & 0xff;
-                    }
-                    OpArgModeBC::REG_OR_CONST => {
-                        value = (instruction.raw >> 17) & 0x1ff;
-                        if value < 0x100 {
-                            mode = OpArgMode::REG;
-                        } else {
-                            mode = OpArgMode::CONST;
-                            value &= 0xff;
-                        }
-                    }
-                    OpArgModeBC::CONST => {
-                        mode = OpArgMode::CONST;
-                        value = (instruction.raw >> 17) & 0xff;
-                    }
-                    _ => {}
-                }
-                instruction.args.push(OpArg { mode, value });
-            }
+impl Failure {
+    const BATCH: i32 = 1;
+    const USAGE: i32 = 2;
+    const IO: i32 = 3;
+    const MALFORMED: i32 = 4;
+    const UNSUPPORTED: i32 = 5;
+    const ASSEMBLE: i32 = 6;
 
-            if opmodes.arg_mode_c != OpArgModeBC::UNUSED {
-                let mut mode: OpArgMode = OpArgMode::NUMBER;
-                let mut value: u32 = 0;
-                match opmodes.arg_mode_c {
-                    OpArgModeBC::NUMBER => {
-                        mode = OpArgMode::NUMBER;
-                        value = instruction.raw >> 8 & 0xff;
-                    }
-                    OpArgModeBC::OFFSET => {
-                        mode = OpArgMode::NUMBER;
-                        value = instruction.raw >> 8 & 0x1ff;
-                    }
-                    OpArgModeBC::REG => {
-                        mode = OpArgMode::REG;
-                        value = (instruction.raw >> 8) & 0xff;
-                    }
-                    OpArgModeBC::REG_OR_CONST => {
-                        value = (instruction.raw >> 8) & 0x1ff;
-                        if value < 0x100 {
-                            mode = OpArgMode::REG;
-                        } else {
-                            mode = OpArgMode::CONST;
-                            value &= 0xff;
-                        }
-                    }
-                    OpArgModeBC::CONST => {
-                        mode = OpArgMode::CONST;
-                        value = (instruction.raw >> 8) & 0xff;
-                    }
-                    _ => {}
-                }
-                instruction.args.push(OpArg { mode, value });
-            }
-        } else if opmodes.arg_mode_b != OpArgModeBC::UNUSED {
-            let mut value = instruction.raw >> 8 & 0x1ffff;
-            if opmodes.mode == OpMode::iAsBx {
-                value -= 0xffff;
-            }
-            let mode = match opmodes.arg_mode_b {
-                OpArgModeBC::OFFSET => OpArgMode::NUMBER,
-                OpArgModeBC::CONST => OpArgMode::CONST,
-                _ => OpArgMode::NUMBER,
+    fn new(code: i32, message: String) -> Self {
+        Failure { code, message }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(failure) = run(&cli) {
+        if !failure.message.is_empty() {
+            eprintln!("{}", failure.message);
+        }
+        std::process::exit(failure.code);
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Failure> {
+    // 0 prints only errors, 1 is the default.
+    let verbosity = if cli.quiet { 0 } else { 1 + cli.verbose };
+    match &cli.command {
+        Command::Info { io, format } => {
+            let chunk = load(&io.input, verbosity)?;
+            let text = match format {
+                InfoFormat::Text => summary(&chunk, verbosity > 1),
+                InfoFormat::Json => to_json(&chunk),
+            };
+            emit(io.output.as_deref(), &text)
+        }
+        Command::Disasm(io) => {
+            let chunk = load(&io.input, verbosity)?;
+            emit(io.output.as_deref(), &disassemble_chunk(&chunk))
+        }
+        Command::DumpConstants { io, format } => {
+            let chunk = load(&io.input, verbosity)?;
+            let format = match format {
+                ConstantsArg::Text => ConstantsFormat::Text,
+                ConstantsArg::Csv => ConstantsFormat::Csv,
             };
-            instruction.args.push(OpArg { mode, value });
+            emit(io.output.as_deref(), &constants(&chunk, format))
         }
+        Command::Cfg { io, format } => {
+            let chunk = load(&io.input, verbosity)?;
+            let text = match format {
+                CfgFormat::Text => cfg_listing(&chunk),
+                CfgFormat::Dot => dot(&chunk),
+            };
+            emit(io.output.as_deref(), &text)
+        }
+        Command::Decompile {
+            inputs,
+            output,
+            intrinsics,
+        } => {
+            let intrinsics = match intrinsics {
+                Some(path) => load_intrinsics(path, verbosity)?,
+                None => Intrinsics::default(),
+            };
+            let batch = inputs.len() > 1
+                || inputs.iter().any(|input| input.is_dir())
+                || output.as_deref().is_some_and(names_directory);
+            if !batch {
+                let chunk = load(&inputs[0], verbosity)?;
+                return emit(output.as_deref(), &decompile_chunk(&chunk, &intrinsics));
+            }
+            decompile_batch(inputs, output.as_deref(), &intrinsics, verbosity)
+        }
+        Command::Assemble { listing, output } => {
+            let source = read(listing, verbosity)?;
+            let bytes = assemble(&String::from_utf8_lossy(&source)).map_err(|err| {
+                Failure::new(
+                    Failure::ASSEMBLE,
+                    format!("Failed to assemble {}: {}", listing.display(), err),
+                )
+            })?;
+            write(output, &bytes)
+        }
+    }
+}
 
-        new_instructions.push(instruction);
+fn read(input: &Path, verbosity: u8) -> Result<Vec<u8>, Failure> {
+    if verbosity > 1 {
+        eprintln!("Reading from file {}", input.display());
     }
+    std::fs::read(input).map_err(|err| {
+        Failure::new(
+            Failure::IO,
+            format!("Failed to read {}: {}", input.display(), err),
+        )
+    })
+}
 
-    unk1sec.instructions = new_instructions;
+fn load(input: &Path, verbosity: u8) -> Result<Chunk, Failure> {
+    let data = read(input, verbosity)?;
+    load_chunk(&data).map_err(|err| {
+        let code = if err.is_unsupported() {
+            Failure::UNSUPPORTED
+        } else {
+            Failure::MALFORMED
+        };
+        Failure::new(
+            code,
+            format!("Failed to parse {}: {}", input.display(), err),
+        )
+    })
+}
 
-    for child in unk1sec.child_functions.iter_mut() {
-        let func_block: FunctionBlock = child.clone().into();
-        let a = parse_instructions(LuaSection::FunctionBlock(Box::new(func_block)));
-        if let LuaSection::FunctionBlock(b) = a {
-            *child = FunctionBlock::into(*b);
+fn load_intrinsics(path: &Path, verbosity: u8) -> Result<Intrinsics, Failure> {
+    let text = read(path, verbosity)?;
+    Intrinsics::from_json(&String::from_utf8_lossy(&text)).map_err(|err| {
+        Failure::new(
+            Failure::USAGE,
+            format!("Failed to load intrinsics from {}: {}", path.display(), err),
+        )
+    })
+}
+
+fn write(output: &Path, data: &[u8]) -> Result<(), Failure> {
+    std::fs::write(output, data).map_err(|err| {
+        Failure::new(
+            Failure::IO,
+            format!("Failed to write {}: {}", output.display(), err),
+        )
+    })
+}
+
+/// Writes `text` to `output`, or to standard output without one.
+fn emit(output: Option<&Path>, text: &str) -> Result<(), Failure> {
+    match output {
+        Some(output) => write(output, text.as_bytes()),
+        None => {
+            print!("{}", text);
+            Ok(())
         }
     }
-
-    LuaSection::FunctionBlock(unk1sec)
 }
 
-fn parse_lua_header(reader: &mut Cursor<Vec<u8>>) -> LuaHeader {
-    let header: LuaHeader = reader.read_be().unwrap();
-    header
+/// Whether `output` names a directory: an existing one, or a path ending
+/// in a separator.
+fn names_directory(output: &Path) -> bool {
+    output.is_dir()
+        || output
+            .as_os_str()
+            .to_string_lossy()
+            .ends_with(std::path::is_separator)
 }
 
-fn parse_lua_section(reader: &mut Cursor<Vec<u8>>, sec_type: LuaSectionType) -> LuaSection {
-    match sec_type {
-        LuaSectionType::TypeConstants => {
-            let section: TypeConstsSection = reader.read_be().unwrap();
-            LuaSection::TypeConstants(section)
+fn decompile_batch(
+    inputs: &[PathBuf],
+    outdir: Option<&Path>,
+    intrinsics: &Intrinsics,
+    verbosity: u8,
+) -> Result<(), Failure> {
+    if let Some(outdir) = outdir {
+        std::fs::create_dir_all(outdir).map_err(|err| {
+            Failure::new(
+                Failure::IO,
+                format!("Failed to create {}: {}", outdir.display(), err),
+            )
+        })?;
+    }
+    let mut files: Vec<PathBuf> = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+        let chunks = find_chunks(input).map_err(|err| {
+            Failure::new(
+                Failure::IO,
+                format!("Failed to search {}: {}", input.display(), err),
+            )
+        })?;
+        if verbosity > 1 {
+            eprintln!("Found {} chunks in {}", chunks.len(), input.display());
         }
-        LuaSectionType::FunctionBlock => {
-            let section: FunctionBlock = reader.read_be().unwrap();
-            LuaSection::FunctionBlock(Box::new(section))
+        files.extend(chunks);
+    }
+    let outcomes = decompile_files(&files, outdir, intrinsics);
+    if verbosity > 0 {
+        print_summary(&files, &outcomes, verbosity > 1);
+    }
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| matches!(outcome, Outcome::Failed(_)))
+        .count();
+    match failed {
+        0 => Ok(()),
+        _ => Err(Failure::new(Failure::BATCH, String::new())),
+    }
+}
+
+/// Prints what became of the inputs of a batch, those fully decompiled
+/// only if `all`, and the totals.
+fn print_summary(inputs: &[PathBuf], outcomes: &[Outcome], all: bool) {
+    let (mut ok, mut partial, mut failed) = (0, 0, 0);
+    for (input, outcome) in inputs.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Ok(output) => {
+                ok += 1;
+                if all {
+                    println!("ok       {} -> {}", input.display(), output.display());
+                }
+            }
+            Outcome::Partial(output, reason) => {
+                partial += 1;
+                println!(
+                    "partial  {} -> {}: {}",
+                    input.display(),
+                    output.display(),
+                    reason
+                );
+            }
+            Outcome::Failed(reason) => {
+                failed += 1;
+                println!("failed   {}: {}", input.display(), reason);
+            }
         }
     }
+    println!(
+        "{} files: {} ok, {} partial, {} failed",
+        inputs.len(),
+        ok,
+        partial,
+        failed
+    );
 }
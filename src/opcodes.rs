@@ -2,9 +2,11 @@
 #![allow(dead_code)]
 #![allow(clippy::upper_case_acronyms)]
 
+use serde::Serialize;
+
 // from https://github.com/soupstream/havok-script-tools/blob/master/HavokScriptToolsCommon/HksOpCodes.cs
 
-#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Serialize)]
 #[repr(u8)]
 pub enum OpCode
 {
@@ -104,9 +106,11 @@ pub enum OpCode
     NUM_OPCODES
 }
 
-impl From<u8> for OpCode {
-    fn from(val: u8) -> Self {
-        match val {
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(val: u8) -> Result<Self, u8> {
+        Ok(match val {
             0 => OpCode::GETFIELD,
             1 => OpCode::TEST,
             2 => OpCode::CALL_I,
@@ -199,12 +203,12 @@ impl From<u8> for OpCode {
             89 => OpCode::CHECKTYPE_D,
             90 => OpCode::GETSLOT_D,
             91 => OpCode::GETGLOBAL_MEM,
-            _ => panic!("Invalid OpCode"),
-        }
+            _ => return Err(val),
+        })
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub enum OpMode {
     #[default]
     iABC,
@@ -231,7 +235,8 @@ pub enum OpArgModeBC {
     CONST
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OpArgMode {
     #[default]
     NUMBER,
@@ -275,8 +280,8 @@ pub static OP_MODES: [OpModes; 92] = [
         opcode: OpCode::CALL_C,
         mode: OpMode::iABC,
         arg_mode_a: OpArgModeA::REG,
-        arg_mode_b: OpArgModeBC::REG,
-        arg_mode_c: OpArgModeBC::REG,
+        arg_mode_b: OpArgModeBC::NUMBER,
+        arg_mode_c: OpArgModeBC::NUMBER,
     },
     OpModes {
         opcode: OpCode::EQ,
@@ -729,14 +734,14 @@ pub static OP_MODES: [OpModes; 92] = [
     OpModes {
         opcode: OpCode::TAILCALL_I_R1,
         mode: OpMode::iABC,
-        arg_mode_a: OpArgModeA::UNUSED,
+        arg_mode_a: OpArgModeA::REG,
         arg_mode_b: OpArgModeBC::NUMBER,
         arg_mode_c: OpArgModeBC::NUMBER,
     },
     OpModes {
         opcode: OpCode::CALL_I_R1,
         mode: OpMode::iABC,
-        arg_mode_a: OpArgModeA::UNUSED,
+        arg_mode_a: OpArgModeA::REG,
         arg_mode_b: OpArgModeBC::NUMBER,
         arg_mode_c: OpArgModeBC::NUMBER,
     },
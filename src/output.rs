@@ -0,0 +1,70 @@
+//! Placement of decompiled chunks in an output directory, following the
+//! script paths recorded in their debug info.
+
+use crate::structs::{Chunk, FunctionBlock};
+use std::path::{Component, Path, PathBuf};
+
+/// Characters that cannot appear in a file name on some platform.
+const RESERVED: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Turns a chunk name from the debug info, such as `@scripts\ui\hud.lua`,
+/// into a relative path under which its source can be written: without the
+/// `@`/`=` prefix, drive letters, `.`/`..` and reserved characters, and
+/// ending in `.lua`. Returns `None` if nothing usable remains.
+pub fn sanitize_source_path(path: &str) -> Option<PathBuf> {
+    let path = path
+        .strip_prefix('@')
+        .or_else(|| path.strip_prefix('='))
+        .unwrap_or(path);
+    let mut out = PathBuf::new();
+    for part in path.split(['/', '\\']) {
+        // A drive letter such as `C:` only makes the path absolute.
+        if part.len() == 2 && part.ends_with(':') && out.as_os_str().is_empty() {
+            continue;
+        }
+        let part: String = part
+            .chars()
+            .map(|c| {
+                if c.is_control() || RESERVED.contains(&c) {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect();
+        let part = part.trim_end_matches(['.', ' ']);
+        if matches!(
+            Path::new(part).components().next(),
+            Some(Component::Normal(_))
+        ) {
+            out.push(part);
+        }
+    }
+    let name = out.file_name()?.to_string_lossy().into_owned();
+    if !name.ends_with(".lua") {
+        out.set_file_name(format!("{}.lua", name));
+    }
+    Some(out)
+}
+
+/// The path under `outdir` the decompiled source of `chunk` is written to:
+/// its original script path, or the name of the file it was read from if
+/// the debug info is stripped.
+pub fn output_path(outdir: &Path, chunk: &Chunk, input: &Path) -> PathBuf {
+    let relative = source_path(&chunk.main_function)
+        .and_then(sanitize_source_path)
+        .unwrap_or_else(|| {
+            let stem = input.file_stem().unwrap_or(input.as_os_str());
+            PathBuf::from(format!("{}.lua", stem.to_string_lossy()))
+        });
+    outdir.join(relative)
+}
+
+/// The script path of the first prototype that records one; a stripped
+/// main chunk may still have closures with debug info.
+fn source_path(func: &FunctionBlock) -> Option<&str> {
+    if func.has_debug_info && !func.debug_info.path.is_empty() {
+        return Some(&func.debug_info.path);
+    }
+    func.child_functions.iter().find_map(source_path)
+}
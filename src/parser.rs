@@ -0,0 +1,243 @@
+use crate::error::{ConstantTypeError, Error};
+use crate::opcodes::*;
+use crate::structs::*;
+use binrw::{BinReaderExt, BinResult, Endian};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+/// Parses a complete format 14 bytecode file.
+pub fn load_chunk(data: &[u8]) -> Result<Chunk, Error> {
+    let mut reader = Tracked {
+        inner: Cursor::new(data),
+        last_read: 0,
+    };
+    let header = read_section(&mut reader, "header", parse_lua_header)?;
+    if header.version != 0x51 {
+        return Err(Error::UnsupportedVersion(header.version));
+    }
+    if header.format != 0xE {
+        return Err(Error::UnsupportedFormat(header.format));
+    }
+
+    // Everything after the header is stored in the byte order and with the
+    // primitive sizes it declares.
+    let endian = Endian::from(header.endianness);
+    let format = header.chunk_format()?;
+    let type_constants = read_section(&mut reader, "type constants", |r| {
+        parse_type_constants(r, endian, format)
+    })?;
+    let mut main_function = read_section(&mut reader, "function block", |r| {
+        parse_function_block(r, endian, format)
+    })?;
+    parse_instructions(&mut main_function)?;
+
+    Ok(Chunk {
+        header,
+        type_constants,
+        main_function,
+    })
+}
+
+/// A reader that remembers where its last read started: binrw rewinds on
+/// failure, so this is the only record of where the data ran out.
+struct Tracked<'a> {
+    inner: Cursor<&'a [u8]>,
+    last_read: u64,
+}
+
+impl Read for Tracked<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.last_read = self.inner.position();
+        self.inner.read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        self.last_read = self.inner.position();
+        self.inner.read_exact(buf)
+    }
+}
+
+impl Seek for Tracked<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Runs `parse` at the reader's position, translating binrw failures into
+/// an [`Error`] that names the section and the offset it went wrong at.
+fn read_section<'a, T>(
+    reader: &mut Tracked<'a>,
+    section: &'static str,
+    parse: impl FnOnce(&mut Tracked<'a>) -> BinResult<T>,
+) -> Result<T, Error> {
+    let start = reader.inner.position();
+    parse(reader).map_err(|err| {
+        if err.is_eof() {
+            return Error::Truncated {
+                section,
+                offset: reader.last_read,
+            };
+        }
+        if let Some(ConstantTypeError(constant_type)) =
+            err.root_cause().custom_err::<ConstantTypeError>()
+        {
+            return Error::BadConstantType {
+                constant_type: *constant_type,
+                offset: error_position(&err).unwrap_or(start),
+            };
+        }
+        match err.root_cause() {
+            binrw::Error::BadMagic { pos, .. } => Error::BadMagic { offset: *pos },
+            _ => Error::Parse {
+                section,
+                offset: error_position(&err).unwrap_or(start),
+                source: err,
+            },
+        }
+    })
+}
+
+fn error_position(err: &binrw::Error) -> Option<u64> {
+    match err.root_cause() {
+        binrw::Error::BadMagic { pos, .. }
+        | binrw::Error::AssertFail { pos, .. }
+        | binrw::Error::Custom { pos, .. }
+        | binrw::Error::NoVariantMatch { pos }
+        | binrw::Error::EnumErrors { pos, .. } => Some(*pos),
+        _ => None,
+    }
+}
+
+/// Decodes opcodes and operands of every instruction in `func` and its
+/// nested prototypes.
+pub fn parse_instructions(func: &mut FunctionBlock) -> Result<(), Error> {
+    let instructions: Vec<LuaInstruction> = std::mem::take(&mut func.instructions);
+
+    let mut new_instructions: Vec<LuaInstruction> = Vec::new();
+
+    for (pc, mut instruction) in instructions.into_iter().enumerate() {
+        let opcode = (instruction.raw >> 25) as u8;
+        instruction.opcode = OpCode::try_from(opcode).map_err(|opcode| Error::UnknownOpCode {
+            opcode,
+            pc,
+            offset: func.instructions_address + pc as u64 * 4,
+        })?;
+        instruction.opmodes = OP_MODES[instruction.opcode as usize];
+        let opmodes = instruction.opmodes;
+        let mode = match instruction.opmodes.arg_mode_a {
+            OpArgModeA::UNUSED => OpArgMode::NUMBER,
+            OpArgModeA::REG => OpArgMode::REG,
+        };
+        let value = instruction.raw & 0xff;
+        instruction.args.push(OpArg { mode, value });
+
+        if opmodes.mode == OpMode::iABC {
+            if opmodes.arg_mode_b != OpArgModeBC::UNUSED {
+                let mut mode: OpArgMode = OpArgMode::NUMBER;
+                let mut value: u32 = 0;
+                match opmodes.arg_mode_b {
+                    OpArgModeBC::NUMBER => {
+                        mode = OpArgMode::NUMBER;
+                        value = instruction.raw >> 17 & 0xff;
+                    }
+                    OpArgModeBC::OFFSET => {
+                        mode = OpArgMode::NUMBER;
+                        value = instruction.raw >> 17 & 0x1ff;
+                    }
+                    OpArgModeBC::REG => {
+                        mode = OpArgMode::REG;
+                        value = (instruction.raw >> 17) & 0xff;
+                    }
+                    OpArgModeBC::REG_OR_CONST => {
+                        value = (instruction.raw >> 17) & 0x1ff;
+                        if value < 0x100 {
+                            mode = OpArgMode::REG;
+                        } else {
+                            mode = OpArgMode::CONST;
+                            value &= 0xff;
+                        }
+                    }
+                    OpArgModeBC::CONST => {
+                        mode = OpArgMode::CONST;
+                        value = (instruction.raw >> 17) & 0xff;
+                    }
+                    _ => {}
+                }
+                instruction.args.push(OpArg { mode, value });
+            }
+
+            if opmodes.arg_mode_c != OpArgModeBC::UNUSED {
+                let mut mode: OpArgMode = OpArgMode::NUMBER;
+                let mut value: u32 = 0;
+                match opmodes.arg_mode_c {
+                    OpArgModeBC::NUMBER => {
+                        mode = OpArgMode::NUMBER;
+                        value = instruction.raw >> 8 & 0xff;
+                    }
+                    OpArgModeBC::OFFSET => {
+                        mode = OpArgMode::NUMBER;
+                        value = instruction.raw >> 8 & 0x1ff;
+                    }
+                    OpArgModeBC::REG => {
+                        mode = OpArgMode::REG;
+                        value = (instruction.raw >> 8) & 0xff;
+                    }
+                    OpArgModeBC::REG_OR_CONST => {
+                        value = (instruction.raw >> 8) & 0x1ff;
+                        if value < 0x100 {
+                            mode = OpArgMode::REG;
+                        } else {
+                            mode = OpArgMode::CONST;
+                            value &= 0xff;
+                        }
+                    }
+                    OpArgModeBC::CONST => {
+                        mode = OpArgMode::CONST;
+                        value = (instruction.raw >> 8) & 0xff;
+                    }
+                    _ => {}
+                }
+                instruction.args.push(OpArg { mode, value });
+            }
+        } else if opmodes.arg_mode_b != OpArgModeBC::UNUSED {
+            let mut value = instruction.raw >> 8 & 0x1ffff;
+            if opmodes.mode == OpMode::iAsBx {
+                value = value.wrapping_sub(0xffff);
+            }
+            let mode = match opmodes.arg_mode_b {
+                OpArgModeBC::OFFSET => OpArgMode::NUMBER,
+                OpArgModeBC::CONST => OpArgMode::CONST,
+                _ => OpArgMode::NUMBER,
+            };
+            instruction.args.push(OpArg { mode, value });
+        }
+
+        new_instructions.push(instruction);
+    }
+
+    func.instructions = new_instructions;
+
+    for child in func.child_functions.iter_mut() {
+        parse_instructions(child)?;
+    }
+    Ok(())
+}
+
+pub fn parse_lua_header<R: Read + Seek>(reader: &mut R) -> BinResult<LuaHeader> {
+    reader.read_be()
+}
+
+pub fn parse_type_constants<R: Read + Seek>(
+    reader: &mut R,
+    endian: Endian,
+    format: ChunkFormat,
+) -> BinResult<TypeConstsSection> {
+    reader.read_type_args(endian, (format,))
+}
+
+pub fn parse_function_block<R: Read + Seek>(
+    reader: &mut R,
+    endian: Endian,
+    format: ChunkFormat,
+) -> BinResult<FunctionBlock> {
+    reader.read_type_args(endian, (0, format))
+}
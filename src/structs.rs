@@ -1,22 +1,36 @@
+use crate::error::{ConstantTypeError, Error};
 use crate::opcodes::*;
-use binrw::{BinRead, PosValue};
+use binrw::{BinRead, BinResult, BinWrite, Endian, PosValue};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
 
-#[derive(BinRead, Debug)]
-#[br(repr = u8)]
+#[derive(BinRead, BinWrite, Serialize, Debug, Clone, Copy)]
+#[brw(repr = u8)]
+#[serde(rename_all = "lowercase")]
 pub enum LuaEndian {
     Big,
     Little,
 }
 
-#[derive(BinRead, Debug)]
-#[br(repr = u8)]
+impl From<LuaEndian> for Endian {
+    fn from(value: LuaEndian) -> Self {
+        match value {
+            LuaEndian::Big => Endian::Big,
+            LuaEndian::Little => Endian::Little,
+        }
+    }
+}
+
+#[derive(BinRead, BinWrite, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[brw(repr = u8)]
+#[serde(rename_all = "lowercase")]
 pub enum LuaNumberType {
     Float,
     Integer,
 }
 
-#[derive(BinRead, Debug)]
-#[br(big, magic = b"\x1bLua")]
+#[derive(BinRead, BinWrite, Serialize, Debug, Clone)]
+#[brw(big, magic = b"\x1bLua")]
 pub struct LuaHeader {
     pub version: u8,
     pub format: u8,
@@ -30,243 +44,594 @@ pub struct LuaHeader {
     pub unk: u8,
 }
 
-#[derive(Debug)]
-pub enum LuaSectionType {
-    TypeConstants,
-    FunctionBlock,
+impl LuaHeader {
+    /// Checks the primitive sizes declared by the header and returns them
+    /// in the form the section parsers take.
+    pub fn chunk_format(&self) -> Result<ChunkFormat, Error> {
+        let sizes = [
+            ("int size", self.int_size),
+            ("size_t size", self.size_t),
+            ("number size", self.number_size),
+        ];
+        for (field, size) in sizes {
+            if size != 4 && size != 8 {
+                return Err(Error::UnsupportedLayout { field, size });
+            }
+        }
+        if self.instruction_size != 4 {
+            return Err(Error::UnsupportedLayout {
+                field: "instruction size",
+                size: self.instruction_size,
+            });
+        }
+        Ok(ChunkFormat {
+            int_size: self.int_size,
+            size_t: self.size_t,
+            number_size: self.number_size,
+            number_type: self.number_type,
+        })
+    }
+}
+
+/// The most registers a function can use, as in Lua's `MAXSTACK`. Counts of
+/// parameters and upvalues beyond it are rejected rather than trusted.
+pub const MAX_REGISTERS: u32 = 250;
+
+/// Widths of the header-dependent primitives, passed to every structure
+/// that contains counts, string lengths or numbers.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkFormat {
+    pub int_size: u8,
+    pub size_t: u8,
+    pub number_size: u8,
+    pub number_type: LuaNumberType,
+}
+
+fn read_sized<R: binrw::io::Read + binrw::io::Seek>(
+    reader: &mut R,
+    endian: Endian,
+    size: u8,
+    what: &str,
+) -> BinResult<u32> {
+    let pos = reader.stream_position()?;
+    let value = match size {
+        8 => u64::read_options(reader, endian, ())?,
+        _ => u32::read_options(reader, endian, ())? as u64,
+    };
+    u32::try_from(value).map_err(|_| binrw::Error::AssertFail {
+        pos,
+        message: format!("{} {} does not fit in 32 bits", what, value),
+    })
+}
+
+/// Reads an `int`, as wide as `ChunkFormat::int_size`.
+#[binrw::parser(reader, endian)]
+fn read_int(format: ChunkFormat) -> BinResult<u32> {
+    read_sized(reader, endian, format.int_size, "int")
+}
+
+/// Reads an `int` holding a boolean, which is true only when it is 1.
+#[binrw::parser(reader, endian)]
+fn read_flag(format: ChunkFormat) -> BinResult<bool> {
+    Ok(read_sized(reader, endian, format.int_size, "int")? == 1)
+}
+
+/// Reads a signed `int`; 8-byte values must fit in 32 bits.
+#[binrw::parser(reader, endian)]
+fn read_signed_int(format: ChunkFormat) -> BinResult<i32> {
+    let pos = reader.stream_position()?;
+    let value = match format.int_size {
+        8 => i64::read_options(reader, endian, ())?,
+        _ => i32::read_options(reader, endian, ())? as i64,
+    };
+    i32::try_from(value).map_err(|_| binrw::Error::AssertFail {
+        pos,
+        message: format!("int {} does not fit in 32 bits", value),
+    })
+}
+
+/// Reads a `size_t`, as wide as `ChunkFormat::size_t`.
+#[binrw::parser(reader, endian)]
+fn read_size_t(format: ChunkFormat) -> BinResult<u32> {
+    read_sized(reader, endian, format.size_t, "size_t")
+}
+
+/// Reads a light userdata pointer, as wide as `ChunkFormat::size_t`.
+#[binrw::parser(reader, endian)]
+fn read_pointer(format: ChunkFormat) -> BinResult<i64> {
+    Ok(match format.size_t {
+        8 => i64::read_options(reader, endian, ())?,
+        _ => u32::read_options(reader, endian, ())? as i64,
+    })
+}
+
+#[binrw::parser(reader, endian)]
+fn read_ints(format: ChunkFormat, count: u32) -> BinResult<Vec<u32>> {
+    (0..count)
+        .map(|_| read_sized(reader, endian, format.int_size, "int"))
+        .collect()
 }
 
-#[derive(Debug, Clone)]
-pub enum LuaSection {
-    TypeConstants(TypeConstsSection),
-    FunctionBlock(Box<FunctionBlock>),
+#[binrw::parser(reader, endian)]
+fn read_number(format: ChunkFormat) -> BinResult<LuaNumber> {
+    Ok(match (format.number_type, format.number_size) {
+        (LuaNumberType::Float, 8) => LuaNumber::F64(f64::read_options(reader, endian, ())?),
+        (LuaNumberType::Float, _) => LuaNumber::F32(f32::read_options(reader, endian, ())?),
+        (LuaNumberType::Integer, 8) => LuaNumber::I64(i64::read_options(reader, endian, ())?),
+        (LuaNumberType::Integer, _) => LuaNumber::I32(i32::read_options(reader, endian, ())?),
+    })
 }
 
-#[derive(BinRead, Debug, Clone)]
-#[br(big)]
+fn write_sized<W: binrw::io::Write + binrw::io::Seek>(
+    writer: &mut W,
+    endian: Endian,
+    size: u8,
+    value: u64,
+) -> BinResult<()> {
+    match size {
+        8 => value.write_options(writer, endian, ()),
+        _ => (value as u32).write_options(writer, endian, ()),
+    }
+}
+
+/// Writes an `int`, as wide as `ChunkFormat::int_size`.
+#[binrw::writer(writer, endian)]
+fn write_int(value: &u32, format: ChunkFormat) -> BinResult<()> {
+    write_sized(writer, endian, format.int_size, *value as u64)
+}
+
+#[binrw::writer(writer, endian)]
+fn write_flag(value: &bool, format: ChunkFormat) -> BinResult<()> {
+    write_sized(writer, endian, format.int_size, *value as u64)
+}
+
+/// Writes a signed `int`, sign-extended when `int` is 8 bytes wide.
+#[binrw::writer(writer, endian)]
+fn write_signed_int(value: &i32, format: ChunkFormat) -> BinResult<()> {
+    write_sized(writer, endian, format.int_size, *value as i64 as u64)
+}
+
+#[binrw::writer(writer, endian)]
+fn write_size_t(value: &u32, format: ChunkFormat) -> BinResult<()> {
+    write_sized(writer, endian, format.size_t, *value as u64)
+}
+
+#[binrw::writer(writer, endian)]
+fn write_pointer(value: &i64, format: ChunkFormat) -> BinResult<()> {
+    write_sized(writer, endian, format.size_t, *value as u64)
+}
+
+#[binrw::writer(writer, endian)]
+fn write_ints(values: &Vec<u32>, format: ChunkFormat) -> BinResult<()> {
+    for value in values {
+        write_sized(writer, endian, format.int_size, *value as u64)?;
+    }
+    Ok(())
+}
+
+#[binrw::writer(writer, endian)]
+fn write_number(value: &LuaNumber) -> BinResult<()> {
+    match value {
+        LuaNumber::F32(n) => n.write_options(writer, endian, ()),
+        LuaNumber::F64(n) => n.write_options(writer, endian, ()),
+        LuaNumber::I32(n) => n.write_options(writer, endian, ()),
+        LuaNumber::I64(n) => n.write_options(writer, endian, ()),
+    }
+}
+
+/// Writes `size` bytes of string data; the NUL terminator and any other
+/// trailing NULs trimmed when reading are restored as padding.
+#[binrw::writer(writer)]
+fn write_string(value: &String, size: u32) -> BinResult<()> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize(size as usize, 0);
+    writer.write_all(&bytes)?;
+    Ok(())
+}
+
+/// A parsed bytecode file: the header, the file-level type constants and
+/// the tree of function prototypes rooted at the main chunk.
+#[derive(Serialize, Debug, Clone)]
+pub struct Chunk {
+    pub header: LuaHeader,
+    pub type_constants: TypeConstsSection,
+    pub main_function: FunctionBlock,
+}
+
+#[derive(BinRead, BinWrite, Serialize, Debug, Clone)]
+#[brw(import(format: ChunkFormat))]
+#[serde(transparent)]
 pub struct TypeConstsSection {
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
+    #[serde(skip)]
     pub constants_amount: u32,
-    #[br(count = constants_amount)]
+    #[br(count = constants_amount, args { inner: (format,) })]
+    #[bw(args(format))]
     pub constants: Vec<LuaConstant>,
 }
 
-#[derive(BinRead, Debug, Clone)]
-#[br(big)]
+#[derive(BinRead, BinWrite, Serialize, Debug, Clone)]
+#[brw(import(format: ChunkFormat))]
 pub struct LuaConstant {
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
+    #[serde(rename = "type")]
     pub constant_type: u32,
+    #[br(parse_with = read_size_t, args(format))]
+    #[bw(write_with = write_size_t, args(format))]
+    #[serde(skip)]
     pub string_size: u32,
 
     #[br(map = |s: Vec<u8>| String::from_utf8_lossy(&s).trim_end_matches('\0').to_string(), count = string_size)]
+    #[bw(write_with = write_string, args(*string_size))]
+    #[serde(rename = "name")]
     pub const_string: String,
 }
 
-#[derive(BinRead, Debug, Clone, Copy)]
-#[br(repr(u8))]
+#[derive(BinRead, BinWrite, Debug, Clone, Copy)]
+#[brw(repr(u8))]
 pub enum VarArgFlags {
+    None = 0,
     Has = 1 << 0,
     IsVar = 1 << 1,
     Unk3 = 3,
     Needs = 1 << 2,
 }
 
-#[derive(BinRead, Debug, Clone)]
-#[br(big)]
+/// Serialized as the raw flag byte.
+impl Serialize for VarArgFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+/// A function prototype. The main chunk is read with `depth` 0 and every
+/// closure below it with its nesting depth, children following their
+/// parent's `function_count` depth-first.
+#[derive(BinRead, BinWrite, Serialize, Debug, Clone)]
+#[br(import(depth: u32, format: ChunkFormat))]
+#[bw(import(format: ChunkFormat))]
 pub struct FunctionBlock {
     #[br(map = |x: PosValue<()>| x.pos)]
+    #[bw(ignore)]
     pub address: u64,
-
-    pub upvalue_count: u32,     // 0x00000000 - upvalue count?
-    pub param_count: u32,       // 0x00000000 - param count?
-    pub vararg: VarArgFlags,    // 0x2 - is vararg?
-    pub unk9: u32,              // 0x00000006 - slot count?
+    #[br(calc = depth)]
+    #[bw(ignore)]
+    pub depth: u32,
+
+    #[br(if(depth > 0), parse_with = read_int, args(format))]
+    #[bw(if(*depth > 0), write_with = write_int, args(format))]
+    pub unk0: u32, // only present on nested prototypes
+    #[br(parse_with = read_int, args(format))]
+    #[br(assert(upvalue_count <= MAX_REGISTERS, "upvalue count {} exceeds {}", upvalue_count, MAX_REGISTERS))]
+    #[bw(write_with = write_int, args(format))]
+    pub upvalue_count: u32, // 0x00000000 - upvalue count?
+    #[br(parse_with = read_int, args(format))]
+    #[br(assert(param_count <= MAX_REGISTERS, "param count {} exceeds {}", param_count, MAX_REGISTERS))]
+    #[bw(write_with = write_int, args(format))]
+    pub param_count: u32, // 0x00000000 - param count?
+    pub vararg: VarArgFlags, // 0x2 - is vararg?
+    #[br(if(depth == 0), parse_with = read_int, args(format))]
+    #[bw(if(*depth == 0), write_with = write_int, args(format))]
+    #[serde(rename = "slots")]
+    pub unk9: u32, // 0x00000006 - slot count? only present on the main chunk
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
+    #[serde(skip)]
     pub instruction_count: u32, // 0x0000000A - instruction count
     // pub unk10: u8, // 0x5F - instruction count is 0 index, 0x4C - instruction count is 1 index???
 
     // #[br(count = if unk10 == 0x4C { instruction_count - 1 } else { instruction_count })]
-    #[br(count = instruction_count, align_before=0x4)]
+    #[br(align_before = 0x4, map = |x: PosValue<()>| x.pos)]
+    #[bw(ignore)]
+    #[serde(skip)]
+    pub instructions_address: u64,
+    #[br(count = instruction_count)]
+    #[bw(align_before = 0x4)]
     pub instructions: Vec<LuaInstruction>,
 
+    #[brw(args(format))]
+    #[serde(rename = "constants")]
     pub consts: BungieConstsSection,
 
-    #[br(map = |x: u32| x == 1)]
+    #[br(parse_with = read_flag, args(format))]
+    #[bw(write_with = write_flag, args(format))]
     pub has_debug_info: bool,
-    #[br(if(has_debug_info))]
+    #[br(if(has_debug_info), args(format))]
+    #[bw(if(*has_debug_info), args(format))]
     pub debug_info: DebugInfo,
 
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
+    #[serde(skip)]
     pub function_count: u32,
-    // pub unk1: u32,
-    #[br(count = function_count)]
-    pub child_functions: Vec<ChildFunction>,
+    #[br(count = function_count, args { inner: (depth + 1, format) })]
+    #[bw(args(format))]
+    #[serde(rename = "children")]
+    pub child_functions: Vec<FunctionBlock>,
 }
 
-#[derive(BinRead, Debug, Clone)]
-#[br(big)]
-pub struct ChildFunction {
-    #[br(map = |x: PosValue<()>| x.pos)]
-    pub address: u64,
-
-    // #[br(map = |x: u32| x == 1)]
-    pub unk0: u32,
-    pub upvalue_count: u32,
-    pub param_count: u32,
-    pub vararg: VarArgFlags,
-    // pub unk9: u32, // 0x00000006 - slot count?
-    pub instruction_count: u32,
-
-    #[br(count = instruction_count, align_before=0x4)]
-    pub instructions: Vec<LuaInstruction>,
-
-    pub consts: BungieConstsSection,
-
-    #[br(map = |x: u32| x == 1)]
-    pub has_debug_info: bool,
-    #[br(if(has_debug_info))]
-    pub debug_info: DebugInfo,
-
-    pub function_count: u32,
-}
-
-impl From<ChildFunction> for FunctionBlock {
-    fn from(value: ChildFunction) -> Self {
-        FunctionBlock {
-            address: value.address,
-            upvalue_count: value.upvalue_count,
-            param_count: value.param_count,
-            vararg: value.vararg,
-            unk9: 0,
-            instruction_count: value.instruction_count,
-            // unk10: 0,
-            instructions: value.instructions,
-            consts: value.consts,
-            has_debug_info: value.has_debug_info,
-            debug_info: value.debug_info,
-            function_count: value.function_count,
-            child_functions: vec![],
-        }
+impl FunctionBlock {
+    /// Whether the function takes `...`.
+    pub fn is_vararg(&self) -> bool {
+        matches!(self.vararg, VarArgFlags::IsVar | VarArgFlags::Unk3)
     }
-}
 
-impl From<FunctionBlock> for ChildFunction {
-    fn from(value: FunctionBlock) -> Self {
-        ChildFunction {
-            address: value.address,
-            unk0: 0,
-            upvalue_count: value.upvalue_count,
-            param_count: value.param_count,
-            vararg: value.vararg,
-            instruction_count: value.instruction_count,
-            instructions: value.instructions,
-            consts: value.consts,
-            has_debug_info: value.has_debug_info,
-            debug_info: value.debug_info,
-            function_count: value.function_count,
+    /// The local variable held in register `reg` at `pc`, if the debug info
+    /// declares one. Active locals occupy the lowest registers in the order
+    /// they were declared.
+    pub fn local_at(&self, pc: usize, reg: u32) -> Option<&DebugLocal> {
+        if !self.has_debug_info {
+            return None;
         }
+        let pc = pc as i32;
+        self.debug_info
+            .locals
+            .iter()
+            .filter(|local| local.start <= pc && pc < local.end)
+            .nth(reg as usize)
     }
 }
 
-#[derive(BinRead, Debug, Clone)]
-#[br(big)]
+#[derive(BinRead, BinWrite, Debug, Clone)]
 pub struct LuaInstruction {
     pub raw: u32,
     // opcode is raw >> 25 cast to OpCode enum
-    #[br(ignore)]
+    #[brw(ignore)]
     pub opcode: OpCode,
     // opmodes is opcode's position in the opmode
-    #[br(ignore)]
+    #[brw(ignore)]
     pub opmodes: OpModes,
     #[brw(ignore)]
     pub args: Vec<OpArg>,
 }
 
+impl LuaInstruction {
+    /// Operand A.
+    pub fn a(&self) -> u32 {
+        self.args[0].value
+    }
+
+    /// Operand B, or Bx for iABx/iAsBx instructions.
+    pub fn b(&self) -> OpArg {
+        if self.opmodes.arg_mode_b == OpArgModeBC::UNUSED {
+            return OpArg::default();
+        }
+        self.args[1]
+    }
+
+    /// Operand C, if the instruction has one.
+    pub fn c(&self) -> OpArg {
+        if self.opmodes.arg_mode_c == OpArgModeBC::UNUSED || self.opmodes.mode != OpMode::iABC {
+            return OpArg::default();
+        }
+        if self.opmodes.arg_mode_b == OpArgModeBC::UNUSED {
+            self.args[1]
+        } else {
+            self.args[2]
+        }
+    }
+
+    /// Signed jump offset of an iAsBx instruction.
+    pub fn sbx(&self) -> i32 {
+        self.b().value as i32
+    }
+}
+
+/// Serialized decoded, as the opcode name, its instruction format and the
+/// operands in order; the signed Bx of iAsBx instructions is written
+/// signed.
+impl Serialize for LuaInstruction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let args: Vec<JsonArg> = self
+            .args
+            .iter()
+            .enumerate()
+            .map(|(index, arg)| JsonArg {
+                mode: arg.mode,
+                value: match self.opmodes.mode {
+                    OpMode::iAsBx if index == 1 => arg.value as i32 as i64,
+                    _ => arg.value as i64,
+                },
+            })
+            .collect();
+        let mut state = serializer.serialize_struct("LuaInstruction", 4)?;
+        state.serialize_field("raw", &self.raw)?;
+        state.serialize_field("opcode", &self.opcode)?;
+        state.serialize_field("mode", &self.opmodes.mode)?;
+        state.serialize_field("args", &args)?;
+        state.end()
+    }
+}
+
+#[derive(Serialize)]
+struct JsonArg {
+    mode: OpArgMode,
+    value: i64,
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct OpArg {
     pub mode: OpArgMode,
     pub value: u32,
 }
 
-#[derive(BinRead, Debug, Clone)]
-#[br(big)]
+#[derive(BinRead, BinWrite, Serialize, Debug, Clone)]
+#[brw(import(format: ChunkFormat))]
+#[serde(transparent)]
 pub struct BungieConstsSection {
     // #[br(pad_before = 0x1)]
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
+    #[serde(skip)]
     pub constants_amount: u32,
-    #[br(count = constants_amount)]
+    #[br(count = constants_amount, args { inner: (format,) })]
+    #[bw(args(format))]
     pub constants: Vec<BungieConstant>,
 }
 
-#[derive(BinRead, Debug, Clone)]
-#[br(big)]
+#[derive(BinRead, BinWrite, Serialize, Debug, Clone)]
+#[brw(import(format: ChunkFormat))]
 pub struct BungieConstant {
+    #[br(assert(matches!(constant_type, 0..=4 | 11), ConstantTypeError(constant_type)))]
+    #[serde(skip)]
     pub constant_type: u8,
-    #[br(args(constant_type))]
+    #[br(args(constant_type, format))]
+    #[bw(args(format))]
+    #[serde(flatten)]
     pub constant: BungieConstantEnum,
 }
 
-#[derive(BinRead, Debug, Clone)]
-#[br(import(constant_type: u8))]
+/// A numeric constant, stored with the width and representation the
+/// chunk header declares.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum LuaNumber {
+    F32(f32),
+    F64(f64),
+    I32(i32),
+    I64(i64),
+}
+
+impl LuaNumber {
+    pub fn as_f64(self) -> f64 {
+        match self {
+            LuaNumber::F32(n) => n as f64,
+            LuaNumber::F64(n) => n,
+            LuaNumber::I32(n) => n as f64,
+            LuaNumber::I64(n) => n as f64,
+        }
+    }
+}
+
+#[derive(BinRead, BinWrite, Serialize, Debug, Clone)]
+#[br(import(constant_type: u8, format: ChunkFormat))]
+#[bw(import(format: ChunkFormat))]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
 pub enum BungieConstantEnum {
     #[br(pre_assert(constant_type == 0))]
     None,
     #[br(pre_assert(constant_type == 1))]
-    Bool(u8),
+    Bool(#[serde(serialize_with = "serialize_bool")] u8),
+    /// A pointer, as wide as `size_t`.
     #[br(pre_assert(constant_type == 2))]
-    LightUserData(i64),
+    LightUserData(
+        #[br(parse_with = read_pointer, args(format))]
+        #[bw(write_with = write_pointer, args(format))]
+        i64,
+    ),
     #[br(pre_assert(constant_type == 3))]
-    Number(f32),
+    Number(
+        #[br(parse_with = read_number, args(format))]
+        #[bw(write_with = write_number)]
+        LuaNumber,
+    ),
     #[br(pre_assert(constant_type == 4))]
-    String(BungieConstantString),
+    String(#[brw(args(format))] BungieConstantString),
+    /// A Havok Script `ui64`, which is 8 bytes whatever the header's
+    /// widths.
     #[br(pre_assert(constant_type == 11))]
     U64(u64),
 }
-#[derive(BinRead, Debug, Default, Clone)]
-#[br(big)]
+
+fn serialize_bool<S: Serializer>(value: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(*value != 0)
+}
+
+#[derive(BinRead, BinWrite, Serialize, Debug, Default, Clone)]
+#[brw(import(format: ChunkFormat))]
+#[serde(transparent)]
 pub struct BungieConstantString {
+    #[br(parse_with = read_size_t, args(format))]
+    #[bw(write_with = write_size_t, args(format))]
+    #[serde(skip)]
     pub string_size: u32,
 
     #[br(map = |s: Vec<u8>| String::from_utf8_lossy(&s).trim_end_matches('\0').to_string(), count = string_size)]
+    #[bw(write_with = write_string, args(*string_size))]
     pub const_string: String,
 }
 
-#[derive(BinRead, Debug, Default, Clone)]
-#[br(big)]
+#[derive(BinRead, BinWrite, Serialize, Debug, Default, Clone)]
+#[brw(import(format: ChunkFormat))]
 pub struct DebugInfo {
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
+    #[serde(skip)]
     pub line_count: u32,
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
+    #[serde(skip)]
     pub locals_count: u32,
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
+    #[serde(skip)]
     pub upvalue_count_2: u32,
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
     pub line_begin: u32,
+    #[br(parse_with = read_int, args(format))]
+    #[bw(write_with = write_int, args(format))]
     pub line_end: u32,
 
+    #[br(parse_with = read_size_t, args(format))]
+    #[bw(write_with = write_size_t, args(format))]
+    #[serde(skip)]
     pub path_string_size: u32,
     #[br(map = |s: Vec<u8>| String::from_utf8_lossy(&s).trim_end_matches('\0').to_string(), count = path_string_size)]
+    #[bw(write_with = write_string, args(*path_string_size))]
     pub path: String,
 
+    #[br(parse_with = read_size_t, args(format))]
+    #[bw(write_with = write_size_t, args(format))]
+    #[serde(skip)]
     pub function_string_size: u32,
     #[br(map = |s: Vec<u8>| String::from_utf8_lossy(&s).trim_end_matches('\0').to_string(), count = function_string_size)]
+    #[bw(write_with = write_string, args(*function_string_size))]
     pub function_name: String,
 
-    #[br(count = line_count)]
+    #[br(parse_with = read_ints, args(format, line_count))]
+    #[bw(write_with = write_ints, args(format))]
     pub lines: Vec<u32>,
 
-    #[br(count = locals_count)]
+    #[br(count = locals_count, args { inner: (format,) })]
+    #[bw(args(format))]
     pub locals: Vec<DebugLocal>,
 
-    #[br(count = upvalue_count_2)]
+    #[br(count = upvalue_count_2, args { inner: (format,) })]
+    #[bw(args(format))]
     pub upvalues: Vec<DebugUpvalue>,
 }
 
-#[derive(BinRead, Debug, Default, Clone)]
-#[br(big)]
+#[derive(BinRead, BinWrite, Serialize, Debug, Default, Clone)]
+#[brw(import(format: ChunkFormat))]
 pub struct DebugLocal {
+    #[br(parse_with = read_size_t, args(format))]
+    #[bw(write_with = write_size_t, args(format))]
+    #[serde(skip)]
     pub string_size: u32,
     #[br(map = |s: Vec<u8>| String::from_utf8_lossy(&s).trim_end_matches('\0').to_string(), count = string_size)]
+    #[bw(write_with = write_string, args(*string_size))]
+    #[serde(rename = "name")]
     pub local_name: String,
+    #[br(parse_with = read_signed_int, args(format))]
+    #[bw(write_with = write_signed_int, args(format))]
     pub start: i32,
+    #[br(parse_with = read_signed_int, args(format))]
+    #[bw(write_with = write_signed_int, args(format))]
     pub end: i32,
 }
 
-#[derive(BinRead, Debug, Default, Clone)]
-#[br(big)]
+#[derive(BinRead, BinWrite, Serialize, Debug, Default, Clone)]
+#[brw(import(format: ChunkFormat))]
+#[serde(transparent)]
 pub struct DebugUpvalue {
+    #[br(parse_with = read_size_t, args(format))]
+    #[bw(write_with = write_size_t, args(format))]
+    #[serde(skip)]
     pub string_size: u32,
     #[br(map = |s: Vec<u8>| String::from_utf8_lossy(&s).trim_end_matches('\0').to_string(), count = string_size)]
+    #[bw(write_with = write_string, args(*string_size))]
     pub string: String,
 }
@@ -0,0 +1,657 @@
+use crate::ast::*;
+use crate::cfg::{Cfg, EdgeKind};
+use crate::decompiler::{Context, Lifter};
+use crate::disasm::instruction_line;
+use crate::opcodes::OpCode;
+use crate::structs::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Raised when a region of the CFG has no structured Lua equivalent.
+struct Unstructured;
+
+type Structured<T> = Result<T, Unstructured>;
+
+/// The innermost loop being structured; `break` jumps to its exit.
+struct LoopContext {
+    header: usize,
+    exit: Option<usize>,
+    body: BTreeSet<usize>,
+}
+
+/// Recovers `if`, `while`, `repeat`, numeric and generic `for` and `break`
+/// from the CFG of a prototype. Regions that cannot be expressed that way
+/// are emitted as a commented disassembly instead.
+pub(crate) struct Structurer<'a> {
+    func: &'a FunctionBlock,
+    cfg: &'a Cfg,
+    lifter: Lifter<'a>,
+    visited: Vec<bool>,
+    loops: Vec<LoopContext>,
+    /// Blocks from which an irreducible cycle is entered, mapped to where
+    /// control continues once every path has left it again.
+    irreducible: BTreeMap<usize, Option<usize>>,
+}
+
+impl<'a> Structurer<'a> {
+    pub(crate) fn new(func: &'a FunctionBlock, cfg: &'a Cfg, context: Context<'a>) -> Self {
+        Structurer {
+            func,
+            cfg,
+            lifter: Lifter::new(func, cfg, context),
+            visited: vec![false; cfg.blocks.len()],
+            loops: Vec::new(),
+            irreducible: irreducible_regions(cfg),
+        }
+    }
+
+    pub(crate) fn structure(&mut self) -> Block {
+        if self.cfg.blocks.is_empty() {
+            return Vec::new();
+        }
+        match self.region(0, None) {
+            Ok(block) => block,
+            Err(Unstructured) => {
+                self.visited.fill(false);
+                self.loops.clear();
+                vec![self.fallback(0, None, None)]
+            }
+        }
+    }
+
+    fn successor(&self, block: usize, kind: EdgeKind) -> Option<usize> {
+        self.cfg.blocks[block]
+            .successors
+            .iter()
+            .find(|edge| edge.kind == kind)
+            .map(|edge| edge.target)
+    }
+
+    fn lift_block(&mut self, block: usize) -> Block {
+        self.visited[block] = true;
+        let block = &self.cfg.blocks[block];
+        self.lifter.lift_range(block.start, block.end)
+    }
+
+    /// Structures the blocks from `start` up to (not including) `stop`.
+    /// When `start` is the header of the innermost loop, it is entered as
+    /// the first block of the loop body rather than as a new loop.
+    fn region(&mut self, start: usize, stop: Option<usize>) -> Structured<Block> {
+        let mut out = Vec::new();
+        let mut current = Some(start);
+        let mut first = true;
+        while let Some(block) = current {
+            let entering_body = first && self.loops.last().map(|l| l.header) == Some(block);
+            if !entering_body {
+                if Some(block) == stop {
+                    break;
+                }
+                if let Some(innermost) = self.loops.last() {
+                    if Some(block) == innermost.exit {
+                        out.push(Stmt::Break);
+                        break;
+                    }
+                    if block == innermost.header {
+                        // A jump back to the header that is not the end of
+                        // the body would need `continue`.
+                        return Err(Unstructured);
+                    }
+                }
+            }
+            if self.visited[block] {
+                return Err(Unstructured);
+            }
+            first = false;
+
+            current = if let Some(&resume) = self.irreducible.get(&block) {
+                out.push(self.fallback(block, resume, stop));
+                resume
+            } else if !entering_body && self.is_loop_header(block) {
+                let exit = self.loop_exit(block);
+                self.guarded(&mut out, block, exit, stop, |s, out| {
+                    s.structure_loop(out, block)
+                })
+            } else if self.cfg.blocks[block].successors.len() == 2 {
+                let merge = self.cfg.ipdom[block].filter(|&m| self.is_merge(m) || Some(m) == stop);
+                self.guarded(&mut out, block, merge, stop, |s, out| {
+                    let stmts = s.lift_block(block);
+                    out.extend(stmts);
+                    s.conditional(out, block, stop)
+                })
+            } else {
+                let stmts = self.lift_block(block);
+                out.extend(stmts);
+                self.terminator(&mut out, block, stop)
+            };
+        }
+        Ok(out)
+    }
+
+    /// Runs `construct`, and if its region turns out to be unstructurable
+    /// replaces it with a commented disassembly of the blocks from `start`
+    /// up to `resume`, continuing at `resume`.
+    fn guarded(
+        &mut self,
+        out: &mut Block,
+        start: usize,
+        resume: Option<usize>,
+        stop: Option<usize>,
+        construct: impl FnOnce(&mut Self, &mut Block) -> Structured<Option<usize>>,
+    ) -> Option<usize> {
+        let visited = self.visited.clone();
+        let depth = self.loops.len();
+        let mut stmts = Vec::new();
+        match construct(self, &mut stmts) {
+            Ok(next) => {
+                out.extend(stmts);
+                next
+            }
+            Err(Unstructured) => {
+                self.visited = visited;
+                self.loops.truncate(depth);
+                out.push(self.fallback(start, resume, stop));
+                resume
+            }
+        }
+    }
+
+    /// Comments out the disassembly of every unvisited block reachable from
+    /// `start` without passing `resume`, `stop` or the innermost loop's
+    /// header and exit.
+    fn fallback(&mut self, start: usize, resume: Option<usize>, stop: Option<usize>) -> Stmt {
+        let mut boundary = vec![resume, stop];
+        if let Some(innermost) = self.loops.last() {
+            boundary.push(Some(innermost.header));
+            boundary.push(innermost.exit);
+        }
+        let mut region = BTreeSet::new();
+        let mut pending = vec![start];
+        while let Some(block) = pending.pop() {
+            if self.visited[block] || !region.insert(block) {
+                continue;
+            }
+            for edge in &self.cfg.blocks[block].successors {
+                if !boundary.contains(&Some(edge.target)) {
+                    pending.push(edge.target);
+                }
+            }
+        }
+
+        let mut text = String::from("unstructured control flow, disassembly follows:");
+        for &block in &region {
+            self.visited[block] = true;
+            let block = &self.cfg.blocks[block];
+            for pc in block.start..block.end {
+                text.push('\n');
+                text.push_str(&instruction_line(self.func, pc).trim().replace('\t', " "));
+            }
+        }
+        text.push_str("\nend of unstructured control flow");
+        Stmt::Comment(text)
+    }
+
+    fn is_loop_header(&self, block: usize) -> bool {
+        !self.latches(block).is_empty()
+    }
+
+    /// Blocks of the natural loop headed by `header`.
+    fn natural_loop(&self, header: usize) -> BTreeSet<usize> {
+        let mut body = BTreeSet::from([header]);
+        let mut pending: Vec<usize> = self.latches(header);
+        while let Some(block) = pending.pop() {
+            if body.insert(block) {
+                pending.extend(
+                    self.cfg.blocks[block]
+                        .predecessors
+                        .iter()
+                        .filter(|&&pred| self.cfg.is_reachable(pred)),
+                );
+            }
+        }
+        body
+    }
+
+    /// Blocks jumping back to `header`. Inside another loop only its body
+    /// counts, so that a loop starting the body of a `for` does not take
+    /// the `FORLOOP` jumping back to it for its own latch.
+    fn latches(&self, header: usize) -> Vec<usize> {
+        let innermost = self.loops.last();
+        let mut latches: Vec<usize> = self.cfg.blocks[header]
+            .predecessors
+            .iter()
+            .copied()
+            .filter(|&pred| self.cfg.is_reachable(pred) && self.cfg.is_back_edge(pred, header))
+            .filter(|pred| innermost.is_none_or(|l| l.header != *pred && l.body.contains(pred)))
+            .collect();
+        latches.dedup();
+        latches
+    }
+
+    /// Blocks outside `body` that it branches to. When there are several,
+    /// those that only lead to a return are left out: they belong to the
+    /// loop as written.
+    fn loop_exits(&self, body: &BTreeSet<usize>) -> BTreeSet<usize> {
+        let mut exits: BTreeSet<usize> = body
+            .iter()
+            .flat_map(|&block| self.cfg.blocks[block].successors.iter())
+            .map(|edge| edge.target)
+            .filter(|target| !body.contains(target))
+            .collect();
+        if exits.len() > 1 {
+            exits.retain(|&target| !self.only_returns(target));
+        }
+        exits
+    }
+
+    /// Whether every path from `start` returns without leaving the blocks
+    /// `start` dominates or reaching an enclosing loop.
+    fn only_returns(&self, start: usize) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![start];
+        while let Some(block) = pending.pop() {
+            if !seen.insert(block) {
+                continue;
+            }
+            let enclosing = self
+                .loops
+                .iter()
+                .any(|l| l.header == block || l.exit == Some(block));
+            if enclosing || !self.cfg.dominates(start, block) {
+                return false;
+            }
+            pending.extend(self.cfg.blocks[block].successors.iter().map(|e| e.target));
+        }
+        true
+    }
+
+    /// The single block a loop leaves to, if there is exactly one.
+    fn loop_exit(&self, header: usize) -> Option<usize> {
+        let exits = self.loop_exits(&self.natural_loop(header));
+        match exits.len() {
+            1 => exits.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// Structures a `while` or `repeat` loop headed by `header` and returns
+    /// the block following it.
+    fn structure_loop(&mut self, out: &mut Block, header: usize) -> Structured<Option<usize>> {
+        let body = self.natural_loop(header);
+        let exits = self.loop_exits(&body);
+        if exits.len() > 1 {
+            return Err(Unstructured);
+        }
+        let exit = exits.first().copied();
+        let latches = self.latches(header);
+        self.loops.push(LoopContext { header, exit, body });
+        let result = self.loop_statement(header, exit, &latches);
+        self.loops.pop();
+        out.push(result?);
+        Ok(exit)
+    }
+
+    fn loop_statement(
+        &mut self,
+        header: usize,
+        exit: Option<usize>,
+        latches: &[usize],
+    ) -> Structured<Stmt> {
+        let insts = &self.func.instructions;
+        let head = &self.cfg.blocks[header];
+        let head_branch = self.cfg.branch_pc(header);
+        let exit_edge = head
+            .successors
+            .iter()
+            .find(|edge| Some(edge.target) == exit)
+            .copied();
+
+        // while cond do ... end: the header only computes the test.
+        let simple_test = head.successors.len() == 2
+            && self.lifter.only_temporaries(head.start, head_branch)
+            && insts[head_branch].opcode != OpCode::TESTSET;
+        let unconditional_latches = latches
+            .iter()
+            .all(|&latch| self.cfg.blocks[latch].successors.len() == 1);
+        if let (true, true, Some(exit_edge)) = (simple_test, unconditional_latches, exit_edge) {
+            let head_stmts = self.lift_block(header);
+            let cond = self.lifter.condition(head_branch);
+            let (cond, body_entry) = match exit_edge.kind {
+                EdgeKind::True => (cond.negate(), self.successor(header, EdgeKind::False)),
+                _ => (cond, self.successor(header, EdgeKind::True)),
+            };
+            let body_entry = body_entry.ok_or(Unstructured)?;
+            let body = if body_entry == header {
+                Vec::new()
+            } else {
+                self.region(body_entry, Some(header))?
+            };
+            if head_stmts.is_empty() {
+                return Ok(Stmt::While(cond, body));
+            }
+            // The test's operands could not all be inlined after all.
+            let mut full = head_stmts;
+            full.push(Stmt::If(cond.negate(), vec![Stmt::Break], Vec::new()));
+            full.extend(body);
+            return Ok(Stmt::While(Expr::Bool(true), full));
+        }
+
+        // repeat ... until cond: a single latch testing whether to leave.
+        if let [latch] = *latches {
+            let latch_block = &self.cfg.blocks[latch];
+            let branch = self.cfg.branch_pc(latch);
+            let leaves = latch_block
+                .successors
+                .iter()
+                .find(|edge| Some(edge.target) == exit && edge.target != header)
+                .copied();
+            let loops_back = latch_block
+                .successors
+                .iter()
+                .any(|edge| edge.target == header);
+            if let (Some(leave), true, true) =
+                (leaves, loops_back, insts[branch].opcode != OpCode::TESTSET)
+            {
+                let mut body = if latch == header {
+                    Vec::new()
+                } else {
+                    self.region(header, Some(latch))?
+                };
+                if self.visited[latch] {
+                    return Err(Unstructured);
+                }
+                body.extend(self.lift_block(latch));
+                let cond = self.lifter.condition(branch);
+                let cond = match leave.kind {
+                    EdgeKind::True => cond,
+                    _ => cond.negate(),
+                };
+                return Ok(Stmt::Repeat(body, cond));
+            }
+        }
+
+        // Anything else is an endless loop left through `break`.
+        let body = self.region(header, Some(header))?;
+        Ok(Stmt::While(Expr::Bool(true), body))
+    }
+
+    /// Handles the unconditional control transfer at the end of `block`
+    /// and returns the next block of the region.
+    fn terminator(&mut self, out: &mut Block, block: usize, stop: Option<usize>) -> Option<usize> {
+        let insts = &self.func.instructions;
+        let last = self.cfg.blocks[block].end - 1;
+        let target = self.cfg.blocks[block].successors.first()?.target;
+        let is_for = match insts[last].opcode {
+            OpCode::FORPREP => true,
+            // A JMP ending the function into a TFORLOOP is taken for a loop
+            // with no body, which is left unstructured.
+            OpCode::JMP => {
+                insts[self.cfg.branch_pc(target)].opcode == OpCode::TFORLOOP
+                    && match self.cfg.block_at(last + 1) {
+                        Some(entry) => self.successor(target, EdgeKind::True) == Some(entry),
+                        None => true,
+                    }
+            }
+            _ => false,
+        };
+        if !is_for {
+            return Some(target);
+        }
+        let exit = self.successor(target, EdgeKind::False);
+        // A loop prepared by the last instruction has no body to enter; it
+        // is left unstructured from its header.
+        let body_entry = self.cfg.block_at(last + 1).unwrap_or(target);
+        let (a, operands) = match insts[last].opcode {
+            OpCode::FORPREP => (insts[last].a(), self.lifter.for_operands(last)),
+            _ => {
+                let a = insts[self.cfg.branch_pc(target)].a();
+                let at = self.cfg.branch_pc(target);
+                (
+                    a,
+                    [a, a + 1, a + 2].map(|reg| self.lifter.variable(reg, at)),
+                )
+            }
+        };
+        let emitted = out.len();
+        let next = self.guarded(out, body_entry, exit, stop, |s, out| {
+            s.structure_for(out, target, last, operands.clone())
+        });
+        match &out[emitted..] {
+            [Stmt::NumericFor(..)] => {}
+            [Stmt::GenericFor(_, exprs, _)] => {
+                // `for k, v in pairs(t)` rather than through the three
+                // hidden registers the iterator call fills.
+                let iterator = match emitted.checked_sub(1).map(|index| &out[index]) {
+                    Some(Stmt::Assign(targets, values))
+                        if targets == exprs && values.len() == 1 =>
+                    {
+                        Some(values.clone())
+                    }
+                    _ => None,
+                };
+                if let Some(iterator) = iterator {
+                    out.remove(emitted - 1);
+                    if let Some(Stmt::GenericFor(_, exprs, _)) = out.last_mut() {
+                        *exprs = iterator;
+                    }
+                }
+            }
+            // The loop was left unstructured: keep the values its FORPREP
+            // would have read.
+            _ => {
+                let assignments: Vec<Stmt> = (a..)
+                    .zip(operands)
+                    .map(|(reg, operand)| (self.lifter.variable(reg, last), operand))
+                    .filter(|(target, operand)| target != operand)
+                    .map(|(target, operand)| Stmt::Assign(vec![target], vec![operand]))
+                    .collect();
+                out.splice(emitted..emitted, assignments);
+            }
+        }
+        next
+    }
+
+    /// Structures the numeric or generic `for` loop prepared by the
+    /// instruction at `prep` and controlled by the `FORLOOP`/`TFORLOOP` in
+    /// `header`, with `operands` holding its three control values.
+    fn structure_for(
+        &mut self,
+        out: &mut Block,
+        header: usize,
+        prep: usize,
+        operands: [Expr; 3],
+    ) -> Structured<Option<usize>> {
+        let insts = &self.func.instructions;
+        let exit = self.successor(header, EdgeKind::False);
+        let body_entry = self.successor(header, EdgeKind::True).ok_or(Unstructured)?;
+        if self.cfg.block_at(prep + 1) != Some(body_entry) && body_entry != header {
+            return Err(Unstructured);
+        }
+        // Only the loop instruction is lifted from the header; anything
+        // before it would be lost.
+        if self.cfg.blocks[header].start != self.cfg.branch_pc(header) {
+            return Err(Unstructured);
+        }
+        let body = self.natural_loop(header);
+        self.loops.push(LoopContext { header, exit, body });
+        self.visited[header] = true;
+        let result = if body_entry == header {
+            Ok(Vec::new())
+        } else {
+            self.region(body_entry, Some(header))
+        };
+        self.loops.pop();
+        let body = result?;
+
+        let looping = &insts[self.cfg.branch_pc(header)];
+        let a = looping.a();
+        let [start, limit, step] = operands;
+        let stmt = match looping.opcode {
+            // The loop variables are in scope from the start of the body.
+            OpCode::FORLOOP => {
+                let var = self.lifter.variable(a + 3, prep + 1);
+                Stmt::NumericFor(var, start, limit, step, body)
+            }
+            OpCode::TFORLOOP => {
+                let count = looping.c().value.max(1);
+                Stmt::GenericFor(
+                    (a + 3..a + 3 + count)
+                        .map(|reg| self.lifter.variable(reg, prep + 1))
+                        .collect(),
+                    vec![start, limit, step],
+                    body,
+                )
+            }
+            _ => return Err(Unstructured),
+        };
+        out.push(stmt);
+        Ok(exit)
+    }
+
+    /// Whether `block` can be where the branches of a conditional meet: it
+    /// must not have been emitted yet and must lie inside the innermost
+    /// loop, since anything outside is only reached through `break`.
+    fn is_merge(&self, block: usize) -> bool {
+        !self.visited[block]
+            && self
+                .loops
+                .last()
+                .is_none_or(|innermost| innermost.body.contains(&block))
+    }
+
+    /// Whether every path from `start` ends in a return or a `break` without
+    /// reaching `other`, `stop` or any block `start` does not dominate.
+    fn terminates(&self, start: usize, other: usize, stop: Option<usize>) -> bool {
+        let innermost = self.loops.last();
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![start];
+        while let Some(block) = pending.pop() {
+            if !seen.insert(block) {
+                continue;
+            }
+            if let Some(innermost) = innermost {
+                if Some(block) == innermost.exit {
+                    continue;
+                }
+                if block == innermost.header {
+                    return false;
+                }
+            }
+            if block == other || Some(block) == stop || !self.cfg.dominates(start, block) {
+                return false;
+            }
+            pending.extend(self.cfg.blocks[block].successors.iter().map(|e| e.target));
+        }
+        true
+    }
+
+    /// Structures the two-way branch ending `block` and returns the block
+    /// where control continues afterwards.
+    fn conditional(
+        &mut self,
+        out: &mut Block,
+        block: usize,
+        stop: Option<usize>,
+    ) -> Structured<Option<usize>> {
+        let branch_pc = self.cfg.branch_pc(block);
+        let branch = &self.func.instructions[branch_pc];
+        if matches!(branch.opcode, OpCode::FORLOOP | OpCode::TFORLOOP) {
+            return Err(Unstructured);
+        }
+        let taken = self.successor(block, EdgeKind::True).ok_or(Unstructured)?;
+        let skipped = self.successor(block, EdgeKind::False).ok_or(Unstructured)?;
+        // `cond` holds when the branch goes to `taken`.
+        let cond = self.lifter.condition(branch_pc);
+        // TESTSET copies its operand only on the taken path, into the
+        // variable its register holds where that path leads.
+        let taken_prefix = match branch.opcode {
+            OpCode::TESTSET => vec![Stmt::Assign(
+                vec![self
+                    .lifter
+                    .variable(branch.a(), self.cfg.blocks[taken].start)],
+                vec![self.lifter.variable(branch.b().value, branch_pc)],
+            )],
+            _ => Vec::new(),
+        };
+
+        let exit = self.loops.last().and_then(|l| l.exit);
+        if exit.is_some() && exit != stop {
+            if Some(taken) == exit && Some(skipped) != exit {
+                let mut then_block = taken_prefix;
+                then_block.push(Stmt::Break);
+                out.push(Stmt::If(cond, then_block, Vec::new()));
+                return Ok(Some(skipped));
+            }
+            if Some(skipped) == exit && Some(taken) != exit {
+                out.push(Stmt::If(cond.negate(), vec![Stmt::Break], Vec::new()));
+                out.extend(taken_prefix);
+                return Ok(Some(taken));
+            }
+        }
+
+        let merge = self.cfg.ipdom[block].filter(|&m| self.is_merge(m) || Some(m) == stop);
+        let (skipped_block, taken_block, next) = match merge {
+            Some(merge) => {
+                let skipped_block = self.branch_region(skipped, Some(merge))?;
+                let taken_block = self.branch_region(taken, Some(merge))?;
+                (skipped_block, taken_block, Some(merge))
+            }
+            None if self.terminates(skipped, taken, stop) => {
+                let skipped_block = self.branch_region(skipped, stop)?;
+                out.push(Stmt::If(cond.negate(), skipped_block, Vec::new()));
+                out.extend(taken_prefix);
+                return Ok(Some(taken));
+            }
+            None if self.terminates(taken, skipped, stop) => {
+                let mut taken_block = taken_prefix;
+                taken_block.extend(self.branch_region(taken, stop)?);
+                out.push(Stmt::If(cond, taken_block, Vec::new()));
+                return Ok(Some(skipped));
+            }
+            None => {
+                let skipped_block = self.branch_region(skipped, stop)?;
+                let taken_block = self.branch_region(taken, stop)?;
+                (skipped_block, taken_block, None)
+            }
+        };
+        let mut taken_prefix = taken_prefix;
+        taken_prefix.extend(taken_block);
+        let taken_block = taken_prefix;
+
+        // Compiled `if c then A else B end` jumps to B when c fails, so the
+        // skipped path is the `then` branch.
+        if skipped_block.is_empty() && !taken_block.is_empty() {
+            out.push(Stmt::If(cond, taken_block, Vec::new()));
+        } else {
+            out.push(Stmt::If(cond.negate(), skipped_block, taken_block));
+        }
+        Ok(next)
+    }
+
+    fn branch_region(&mut self, start: usize, stop: Option<usize>) -> Structured<Block> {
+        if Some(start) == stop {
+            return Ok(Vec::new());
+        }
+        self.region(start, stop)
+    }
+}
+
+/// Finds, for every irreducible cycle, the closest block dominating all of
+/// its entries and the first block after it that every path reaches.
+fn irreducible_regions(cfg: &Cfg) -> BTreeMap<usize, Option<usize>> {
+    let mut regions = BTreeMap::new();
+    for cycle in cfg.irreducible_cycles() {
+        let mut blocks = cycle.iter().copied();
+        let Some(first) = blocks.next() else {
+            continue;
+        };
+        let head = blocks.fold(first, |dom, block| cfg.common_dominator(dom, block));
+        let mut resume = cfg.ipdom[head];
+        while let Some(block) = resume {
+            if !cycle.contains(&block) {
+                break;
+            }
+            resume = cfg.ipdom[block];
+        }
+        regions.insert(head, resume);
+    }
+    regions
+}
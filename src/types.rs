@@ -0,0 +1,124 @@
+//! The type registry of a chunk: the value types and engine types its
+//! type constants name, by id.
+
+use crate::ast::is_identifier;
+use crate::structs::TypeConstsSection;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Names of the Havok Script type tags, by tag.
+const TYPE_TAGS: [&str; 13] = [
+    "nil",
+    "boolean",
+    "lightuserdata",
+    "number",
+    "string",
+    "table",
+    "function",
+    "userdata",
+    "thread",
+    "ifunction",
+    "cfunction",
+    "ui64",
+    "struct",
+];
+
+/// Where a type comes from.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeKind {
+    /// One of the value types of the language, listed under its own type
+    /// tag.
+    Builtin,
+    /// A type the engine defines, such as a structure.
+    Engine,
+}
+
+impl TypeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TypeKind::Builtin => "builtin",
+            TypeKind::Engine => "engine",
+        }
+    }
+}
+
+/// One type of the registry.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TypeEntry {
+    pub id: u32,
+    pub name: String,
+    pub kind: TypeKind,
+}
+
+impl std::fmt::Display for TypeEntry {
+    /// `id name (kind)`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} ({})", self.id, self.name, self.kind.as_str())
+    }
+}
+
+/// The types a chunk's type constants declare, in id order. Of several
+/// constants with the same id the first is kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeRegistry {
+    entries: BTreeMap<u32, TypeEntry>,
+}
+
+impl TypeRegistry {
+    pub fn new(section: &TypeConstsSection) -> Self {
+        let mut entries = BTreeMap::new();
+        for constant in &section.constants {
+            let id = constant.constant_type;
+            entries.entry(id).or_insert_with(|| TypeEntry {
+                id,
+                name: constant.const_string.clone(),
+                kind: if TYPE_TAGS.get(id as usize) == Some(&constant.const_string.as_str()) {
+                    TypeKind::Builtin
+                } else {
+                    TypeKind::Engine
+                },
+            });
+        }
+        TypeRegistry { entries }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&TypeEntry> {
+        self.entries.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TypeEntry> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The name of type `id` if it has one usable in source.
+    fn identifier(&self, id: u32) -> Option<String> {
+        self.get(id)
+            .map(|entry| &entry.name)
+            .filter(|name| is_identifier(name))
+            .cloned()
+    }
+
+    /// The name of structure type `id`: its registered name, or
+    /// `struct_<id>` if it has none.
+    pub fn struct_name(&self, id: u32) -> String {
+        self.identifier(id)
+            .unwrap_or_else(|| format!("struct_{}", id))
+    }
+
+    /// The name of type `id` in a type annotation: its registered name,
+    /// else the name of the type tag `id`, else `type_<id>`.
+    pub fn annotation(&self, id: u32) -> String {
+        self.identifier(id)
+            .or_else(|| TYPE_TAGS.get(id as usize).map(|tag| tag.to_string()))
+            .unwrap_or_else(|| format!("type_{}", id))
+    }
+}
@@ -0,0 +1,30 @@
+use crate::error::Error;
+use crate::structs::*;
+use binrw::{BinResult, BinWrite, BinWriterExt, Endian};
+use std::io::Cursor;
+
+/// Serializes a chunk into the binary layout its header declares. A chunk
+/// returned by [`crate::load_chunk`] is written back byte-for-byte, except
+/// for what the parser does not keep: bytes after the main function, the
+/// contents of alignment padding and strings that are not valid UTF-8.
+pub fn write_chunk(chunk: &Chunk) -> Result<Vec<u8>, Error> {
+    let endian = Endian::from(chunk.header.endianness);
+    let format = chunk.header.chunk_format()?;
+    let mut writer = Cursor::new(Vec::new());
+    write_section(&mut writer, "header", |w| w.write_be(&chunk.header))?;
+    write_section(&mut writer, "type constants", |w| {
+        chunk.type_constants.write_options(w, endian, (format,))
+    })?;
+    write_section(&mut writer, "function block", |w| {
+        chunk.main_function.write_options(w, endian, (format,))
+    })?;
+    Ok(writer.into_inner())
+}
+
+fn write_section(
+    writer: &mut Cursor<Vec<u8>>,
+    section: &'static str,
+    write: impl FnOnce(&mut Cursor<Vec<u8>>) -> BinResult<()>,
+) -> Result<(), Error> {
+    write(writer).map_err(|source| Error::Write { section, source })
+}
@@ -0,0 +1,23 @@
+use bungie_lua_decompiler::asm::assemble;
+use bungie_lua_decompiler::error::Error;
+
+// Values that do not fit their field are errors rather than wrapped.
+#[test]
+fn out_of_range_values() {
+    for (listing, line) in [
+        (".function main\n.vararg 258\nRETURN 0 1\n.end\n", 2),
+        (".function main\n[-1] RETURN 0 1\n.end\n", 2),
+        (
+            ".function main\n.const lightuserdata 0x100000000\nRETURN 0 1\n.end\n",
+            2,
+        ),
+    ] {
+        match assemble(listing) {
+            Err(Error::Assemble { line: at, message }) => {
+                assert_eq!(at, line);
+                assert!(message.contains("out of range"), "{}", message);
+            }
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use bungie_lua_decompiler::batch::{find_chunks, process_files, Outcome};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn sample() -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/closures.luac")).unwrap()
+}
+
+// A link back to an ancestor directory is not followed, while a link to a
+// chunk is found like the chunk itself.
+#[cfg(unix)]
+#[test]
+fn symlinks_are_not_walked() {
+    let dir = TempDir::new().unwrap();
+    let nested = dir.path().join("scripts");
+    std::fs::create_dir(&nested).unwrap();
+    std::fs::write(nested.join("a.luac"), sample()).unwrap();
+    std::fs::write(nested.join("notes.txt"), "not a chunk").unwrap();
+    std::os::unix::fs::symlink(dir.path(), nested.join("loop")).unwrap();
+    std::os::unix::fs::symlink(nested.join("a.luac"), nested.join("b.luac")).unwrap();
+
+    assert_eq!(
+        find_chunks(dir.path()).unwrap(),
+        [nested.join("a.luac"), nested.join("b.luac")]
+    );
+}
+
+// A panic fails its own file, with where it happened, and no other.
+#[test]
+fn panics_are_isolated() {
+    let inputs: Vec<PathBuf> = ["a", "b", "c", "d"].iter().map(PathBuf::from).collect();
+    let outcomes = process_files(&inputs, |input| {
+        if input == Path::new("b") {
+            panic!("bad input");
+        }
+        Outcome::Ok(input.with_extension("lua"))
+    });
+
+    assert_eq!(outcomes.len(), 4);
+    for (input, outcome) in inputs.iter().zip(&outcomes) {
+        match outcome {
+            Outcome::Failed(reason) => {
+                assert_eq!(input, Path::new("b"));
+                assert!(
+                    reason.starts_with("panicked at tests/batch.rs:"),
+                    "{reason}"
+                );
+                assert!(reason.ends_with(": bad input"), "{reason}");
+            }
+            Outcome::Ok(output) => assert_eq!(output, &input.with_extension("lua")),
+            Outcome::Partial(..) => panic!("unexpected outcome {outcome:?}"),
+        }
+    }
+}
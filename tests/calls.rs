@@ -0,0 +1,44 @@
+mod common;
+
+const LISTING: &str = r#"
+.function main
+.vararg 2
+.slots 6
+.const "f"
+.const "g"
+.const "obj"
+.const "m"
+.debug "@calls.lua" "" 0 0
+.local "a" 4 12
+.local "b" 4 12
+.local "c" 6 12
+GETGLOBAL 0 K(0)
+GETGLOBAL 1 K(1)
+CALL_I_R1 1 1 0
+CALL_C 0 2 3
+GETGLOBAL 2 K(0)
+CALL_I_R1 2 1 0
+GETGLOBAL 3 K(2)
+SELF 3 3 K(3)
+MOVE 5 2
+CALL_M 3 3 1
+GETGLOBAL 3 K(1)
+VARARG 4 0
+TAILCALL_I_R1 3 0 0
+RETURN 3 0
+.end
+"#;
+
+// The specialized calls take the operands of CALL, except that the `_R1`
+// calls keep one result whatever their C.
+#[test]
+fn specialized_calls() {
+    assert_eq!(
+        common::decompile(LISTING),
+        "-- calls.lua\n\
+         local a, b = f(g())\n\
+         local c = f()\n\
+         obj:m(c)\n\
+         return g(...)\n"
+    );
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use bungie_lua_decompiler::cfg::Cfg;
+
+/// The CFG of a one-parameter main function running `code`, then returning.
+fn cfg(code: &str) -> Cfg {
+    let listing = format!(
+        ".function main\n.params 1\n.slots 4\n.const 1\n{}RETURN 0 1\n.end\n",
+        code
+    );
+    Cfg::new(&common::load(&listing).main_function)
+}
+
+// if a then ... else ... end: both arms are dominated by the test and
+// post-dominated by the join.
+#[test]
+fn diamond() {
+    let cfg = cfg("TEST 0 0\n\
+                   JMP 0 2\n\
+                   LOADK 1 K(0)\n\
+                   JMP 0 1\n\
+                   LOADK 1 K(0)\n");
+    assert_eq!(cfg.blocks.len(), 4);
+    assert_eq!(cfg.idom, [None, Some(0), Some(0), Some(0)]);
+    assert_eq!(cfg.ipdom, [Some(3), Some(3), Some(3), None]);
+    assert!(cfg.dominates(0, 3));
+    assert!(!cfg.dominates(1, 3) && !cfg.dominates(2, 3));
+    assert!(cfg.post_dominates(3, 1) && cfg.post_dominates(3, 2));
+    assert!(!cfg.post_dominates(1, 0));
+    assert!(cfg.irreducible_cycles().is_empty());
+}
+
+// Two nested while loops: each latch jumps back to a header dominating it.
+#[test]
+fn nested_loops() {
+    let cfg = cfg("LOADK 1 K(0)\n\
+                   LT 0 1 0\n\
+                   JMP 0 5\n\
+                   LT 0 1 0\n\
+                   JMP 0 2\n\
+                   ADD 1 1 K(0)\n\
+                   JMP 0 -4\n\
+                   JMP 0 -7\n");
+    assert_eq!(cfg.blocks.len(), 6);
+    assert_eq!(
+        cfg.idom,
+        [None, Some(0), Some(1), Some(2), Some(2), Some(1)]
+    );
+    assert_eq!(
+        cfg.ipdom,
+        [Some(1), Some(5), Some(4), Some(2), Some(1), None]
+    );
+    assert!(cfg.is_back_edge(3, 2));
+    assert!(cfg.is_back_edge(4, 1));
+    assert!(!cfg.is_back_edge(1, 2) && !cfg.is_back_edge(2, 4));
+    assert!(cfg.dominates(1, 3) && !cfg.dominates(3, 4));
+    assert!(cfg.irreducible_cycles().is_empty());
+}
+
+// A cycle jumped into at both of its blocks has no header dominating the
+// other block.
+#[test]
+fn irreducible_cycle() {
+    let cfg = cfg("TEST 0 0\n\
+                   JMP 0 3\n\
+                   ADD 1 1 K(0)\n\
+                   TEST 1 0\n\
+                   JMP 0 2\n\
+                   ADD 1 1 K(0)\n\
+                   JMP 0 -5\n");
+    assert_eq!(cfg.idom, [None, Some(0), Some(0), Some(1)]);
+    assert!(!cfg.is_back_edge(2, 1) && !cfg.is_back_edge(1, 2));
+    let cycles: Vec<Vec<usize>> = cfg
+        .irreducible_cycles()
+        .into_iter()
+        .map(|cycle| cycle.into_iter().collect())
+        .collect();
+    assert_eq!(cycles, [[1, 2]]);
+}
@@ -0,0 +1,117 @@
+use assert_cmd::Command;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn cli() -> Command {
+    Command::cargo_bin("bungie-lua-decompiler").unwrap()
+}
+
+fn sample() -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/closures.luac")).unwrap()
+}
+
+/// Writes `data` to `name` in `dir`.
+fn file(dir: &TempDir, name: &str, data: &[u8]) -> PathBuf {
+    let path = dir.path().join(name);
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[test]
+fn exit_codes() {
+    let dir = TempDir::new().unwrap();
+    let good = file(&dir, "good.luac", &sample());
+    let truncated = file(&dir, "truncated.luac", &sample()[..40]);
+    let mut version = sample();
+    version[4] = 0x52;
+    let unsupported = file(&dir, "unsupported.luac", &version);
+    let listing = file(&dir, "bad.lst", b".function main\nNOTANOP 0\n.end\n");
+    let table = file(&dir, "intrinsics.json", br#"{"1": "not a name"}"#);
+    let missing = dir.path().join("missing.luac");
+
+    cli().arg("info").arg(&good).assert().success();
+    cli().arg("info").arg(&truncated).assert().code(4);
+    cli().arg("info").arg(&unsupported).assert().code(5);
+    cli().arg("info").arg(&missing).assert().code(3);
+    cli()
+        .arg("assemble")
+        .arg(&listing)
+        .arg("-o")
+        .arg(dir.path().join("out.luac"))
+        .assert()
+        .code(6);
+    cli().arg("info").arg("--no-such-flag").assert().code(2);
+    cli()
+        .arg("decompile")
+        .arg(&good)
+        .arg("--intrinsics")
+        .arg(&table)
+        .assert()
+        .code(2);
+    cli()
+        .arg("decompile")
+        .arg(&good)
+        .arg(&truncated)
+        .arg("-o")
+        .arg(dir.path().join("out"))
+        .assert()
+        .code(1);
+}
+
+// An output ending in a separator is a directory even for a single input,
+// and is created along with the script path under it.
+#[test]
+fn output_directory() {
+    let dir = TempDir::new().unwrap();
+    let good = file(&dir, "good.luac", &sample());
+    let outdir = dir.path().join("out");
+    let mut output = outdir.clone().into_os_string();
+    output.push(std::path::MAIN_SEPARATOR_STR);
+
+    cli()
+        .arg("decompile")
+        .arg(&good)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    let written: Vec<_> = walk(&outdir);
+    assert_eq!(written.len(), 1, "{:?}", written);
+    assert_eq!(written[0].extension().unwrap(), "lua");
+}
+
+/// Every file under `dir`, recursively.
+fn walk(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(walk(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+// Without `-o` a batch writes `<name>.dec.lua` next to each input, even one
+// already named `.lua`, and fails the file whose output is another input.
+#[test]
+fn inputs_never_overwritten() {
+    let dir = TempDir::new().unwrap();
+    let named_lua = file(&dir, "compiled.lua", &sample());
+    let first = file(&dir, "first.luac", &sample());
+    let clashing = file(&dir, "first.dec.lua", &sample());
+
+    cli()
+        .arg("decompile")
+        .arg(&named_lua)
+        .arg(&first)
+        .arg(&clashing)
+        .assert()
+        .code(1);
+    assert_eq!(std::fs::read(&named_lua).unwrap(), sample());
+    assert_eq!(std::fs::read(&clashing).unwrap(), sample());
+    assert!(dir.path().join("compiled.dec.lua").is_file());
+    assert!(dir.path().join("first.dec.dec.lua").is_file());
+}
@@ -0,0 +1,26 @@
+//! Fixtures shared by the integration tests: chunks assembled from listings.
+
+// Every test crate compiles this module but uses only some of it.
+#![allow(dead_code)]
+
+use bungie_lua_decompiler::asm::assemble;
+use bungie_lua_decompiler::decompiler::decompile_chunk;
+use bungie_lua_decompiler::intrinsics::Intrinsics;
+use bungie_lua_decompiler::load_chunk;
+use bungie_lua_decompiler::structs::Chunk;
+
+/// Assembles `listing` and parses the bytecode back.
+pub fn load(listing: &str) -> Chunk {
+    load_chunk(&assemble(listing).unwrap()).unwrap()
+}
+
+/// Decompiles the chunk assembled from `listing`, without intrinsic names.
+pub fn decompile(listing: &str) -> String {
+    decompile_with(listing, &Intrinsics::default())
+}
+
+/// Decompiles the chunk assembled from `listing`, naming intrinsics after
+/// `intrinsics`.
+pub fn decompile_with(listing: &str, intrinsics: &Intrinsics) -> String {
+    decompile_chunk(&load(listing), intrinsics)
+}
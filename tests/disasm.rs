@@ -0,0 +1,29 @@
+use bungie_lua_decompiler::asm::assemble;
+use bungie_lua_decompiler::disasm::disassemble_chunk;
+use bungie_lua_decompiler::load_chunk;
+
+// Debug names are escaped in the `.function` comment, so a newline in one
+// cannot start a line of its own.
+#[test]
+fn function_comment_escapes_debug_info() {
+    let listing = "\
+.function main
+.params 0
+.vararg 2
+.slots 2
+.debug \"@dir\\nRETURN 0 1\" \"main\\\\\\\"x\" 1 4
+[1] RETURN 0 1
+.end
+";
+    let data = assemble(listing).unwrap();
+    let disassembled = disassemble_chunk(&load_chunk(&data).unwrap());
+    let comment = disassembled
+        .lines()
+        .find(|line| line.starts_with(".function main"))
+        .unwrap();
+    assert_eq!(
+        comment,
+        ".function main ; \"main\\\\\\\"x\" <\"@dir\\nRETURN 0 1\":1,4> (1 instructions at 0x12)"
+    );
+    assert_eq!(assemble(&disassembled).unwrap(), data);
+}
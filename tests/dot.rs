@@ -0,0 +1,50 @@
+mod common;
+
+use bungie_lua_decompiler::dot::dot;
+
+// if not a then x = 1 end, then a closure: one cluster per prototype,
+// branch edges labelled by what the test does, and a dashed edge from the
+// CLOSURE to the prototype it creates.
+#[test]
+fn clusters_and_edges() {
+    let chunk = common::load(
+        ".function main\n.params 1\n.slots 4\n.const 1\n\
+         TEST 0 0\nJMP 0 1\nLOADK 1 K(0)\nCLOSURE 1 0\nRETURN 0 1\n\
+         .function main.0\n.unk0 0\n.upvalues 0\n.params 0\n.vararg 0\n\
+         RETURN 0 1\n.end\n.end\n",
+    );
+    let graph = dot(&chunk);
+    let lines: Vec<&str> = graph.lines().map(str::trim).collect();
+    for expected in [
+        "subgraph cluster_main {",
+        "subgraph cluster_main_0 {",
+        "main_b0 -> main_b2 [label=\"taken\", color=darkgreen];",
+        "main_b0 -> main_b1 [label=\"not taken\", color=red];",
+        "main_b1 -> main_b2 [label=\"fallthrough\", color=gray40];",
+        "main_b2 -> main_0_b0 [style=dashed, label=\"closure\"];",
+    ] {
+        assert!(
+            lines.contains(&expected),
+            "{} missing from\n{}",
+            expected,
+            graph
+        );
+    }
+    assert_eq!(graph.matches("subgraph").count(), 2);
+    assert_eq!(graph.matches(" -> ").count(), 4);
+}
+
+// Loops are drawn with their back edge marked.
+#[test]
+fn loop_back_edge() {
+    let chunk = common::load(
+        ".function main\n.params 1\n.slots 4\n\
+         TEST 0 0\nJMP 0 1\nJMP 0 -3\nRETURN 0 1\n.end\n",
+    );
+    let graph = dot(&chunk);
+    assert!(
+        graph.contains("main_b1 -> main_b0 [label=\"loop-back\", color=blue];"),
+        "{}",
+        graph
+    );
+}
@@ -0,0 +1,31 @@
+use bungie_lua_decompiler::ast::{BinOp, Expr, LuaWriter, UnOp};
+
+fn text(expr: Expr) -> String {
+    LuaWriter::new().expr(&expr)
+}
+
+// Minus signs never merge into a comment, and negative literals keep their
+// sign inside an exponentiation's base.
+#[test]
+fn negations_keep_their_meaning() {
+    let x = Expr::Global("x".to_string());
+    let neg = |e| Expr::unary(UnOp::Neg, e);
+    assert_eq!(text(neg(neg(x.clone()))), "- -x");
+    assert_eq!(text(neg(Expr::Number(-2.0))), "- -2");
+    assert_eq!(
+        text(Expr::binary(
+            BinOp::Pow,
+            Expr::Number(-2.0),
+            Expr::Number(2.0)
+        )),
+        "(-2) ^ 2"
+    );
+    assert_eq!(
+        text(Expr::binary(BinOp::Pow, neg(x.clone()), Expr::Number(2.0))),
+        "(-x) ^ 2"
+    );
+    assert_eq!(
+        text(Expr::binary(BinOp::Add, Expr::Number(-2.0), x)),
+        "-2 + x"
+    );
+}
@@ -0,0 +1,134 @@
+mod common;
+
+// A closure stored under the name the debug info gives it is declared as
+// a function, with its parameters and `...`, headed by where it was
+// compiled from, as the chunk is.
+#[test]
+fn named_function() {
+    let listing = r#"
+.function main
+.slots 2
+.const "greet"
+.debug "@funcs.lua" "" 0 0
+ [3] CLOSURE 0 0
+ [3] SETGLOBAL 0 K(0)
+ [4] RETURN 0 1
+.function main.0
+.params 1
+.vararg 2
+.slots 2
+.debug "@funcs.lua" "greet" 1 3
+.local "name" 0 1
+ [3] RETURN 0 1
+.end
+.end
+"#;
+    assert_eq!(
+        common::decompile(listing),
+        "-- funcs.lua\n-- funcs.lua:1-3\nfunction greet(name, ...)\nend\n"
+    );
+}
+
+// `local f` followed by `f = function` is a `local function`, whose body
+// sees `f` as an upvalue.
+#[test]
+fn local_function() {
+    let listing = r#"
+.function main
+.slots 2
+.debug "@funcs.lua" "" 0 0
+.local "count" 0 3
+ [3] CLOSURE 0 0
+ [3] MOVE 0 0
+ [3] RETURN 0 1
+.function main.0
+.params 1
+.upvalues 1
+.slots 4
+.debug "@funcs.lua" "count" 1 3
+.local "n" 0 3
+.upvalue "count"
+ [2] GETUPVAL 1 0
+ [2] MOVE 2 0
+ [2] TAILCALL 1 2 0
+ [2] RETURN 1 0
+ [3] RETURN 0 1
+.end
+.end
+"#;
+    assert_eq!(
+        common::decompile(listing),
+        "-- funcs.lua\n-- funcs.lua:1-3\nlocal function count(n)\n  return count(n)\nend\n"
+    );
+}
+
+// Closures that declare nothing are commented with their name and source.
+#[test]
+fn anonymous_closure() {
+    let listing = r#"
+.function main
+.slots 4
+.const "helper"
+.debug "@funcs.lua" "" 0 0
+ [4] GETGLOBAL 0 K(0)
+ [4] CLOSURE 1 0
+ [4] CALL 0 2 1
+ [5] RETURN 0 1
+.function main.0
+.slots 2
+.debug "@funcs.lua" "callback" 4 4
+ [4] RETURN 0 1
+.end
+.end
+"#;
+    assert_eq!(
+        common::decompile(listing),
+        "-- funcs.lua\nhelper(function() -- callback (funcs.lua:4)\nend)\n"
+    );
+}
+
+// Closures stored to fields are declared under the field path, and as
+// methods, with `self` implicit, when their first parameter is `self`.
+#[test]
+fn field_functions_and_methods() {
+    let listing = r#"
+.function main
+.slots 4
+.const "Module"
+.const "Name"
+.const "obj"
+.const "method"
+.debug "@methods.lua" "" 0 0
+ [2] GETGLOBAL 0 K(0)
+ [2] CLOSURE 1 0
+ [2] SETFIELD 0 K(1) 1
+ [3] GETGLOBAL 0 K(2)
+ [3] CLOSURE 1 1
+ [3] SETTABLE_S_BK 0 K(3) 1
+ [4] RETURN 0 1
+.function main.0
+.params 2
+.vararg 2
+.slots 4
+.debug "@methods.lua" "" 2 2
+.local "a" 0 1
+.local "b" 0 1
+ [2] RETURN 0 1
+.end
+.function main.1
+.params 2
+.slots 4
+.debug "@methods.lua" "" 3 3
+.local "self" 0 1
+.local "x" 0 1
+ [3] RETURN 0 1
+.end
+.end
+"#;
+    assert_eq!(
+        common::decompile(listing),
+        "-- methods.lua\n\
+         -- methods.lua:2\nfunction Module.Name(a, b, ...)\nend\n\
+         -- methods.lua:3\nfunction obj:method(x)\nend\n"
+    );
+}
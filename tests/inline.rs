@@ -0,0 +1,55 @@
+mod common;
+
+/// Decompiles a main function running `code` with the constants `f`, `x`,
+/// `y` and 1.
+fn decompile(code: &str) -> String {
+    common::decompile(&format!(
+        ".function main\n.slots 4\n.const \"f\"\n.const \"x\"\n.const \"y\"\n.const 1\n\
+         {}RETURN 0 1\n.end\n",
+        code
+    ))
+}
+
+// Temporaries read once, in the order they were computed, are inlined.
+#[test]
+fn single_uses_are_inlined() {
+    assert_eq!(
+        decompile("GETGLOBAL 0 K(0)\nLOADK 1 K(3)\nCALL 0 2 1\n"),
+        "f(1)\n"
+    );
+    assert_eq!(
+        decompile("GETGLOBAL 0 K(1)\nGETGLOBAL 1 K(0)\nCALL 1 1 2\nADD 0 0 1\nSETGLOBAL 0 K(2)\n"),
+        "y = x + f()\n"
+    );
+}
+
+#[test]
+fn multiple_uses_are_kept() {
+    assert_eq!(
+        decompile("GETGLOBAL 0 K(1)\nADD 0 0 0\nSETGLOBAL 0 K(2)\n"),
+        "local l_0_0 = x\ny = l_0_0 + l_0_0\n"
+    );
+}
+
+// Inlining `x` into the assignment would read it after the call, which
+// may change it.
+#[test]
+fn uses_across_a_call_are_kept() {
+    assert_eq!(
+        decompile("GETGLOBAL 0 K(1)\nGETGLOBAL 1 K(0)\nCALL 1 1 1\nSETGLOBAL 0 K(2)\n"),
+        "local l_0_0 = x\nf()\ny = l_0_0\n"
+    );
+}
+
+// A value still live at the end of its block is read by whichever branch
+// runs, so it stays in its register.
+#[test]
+fn live_out_values_are_kept() {
+    assert_eq!(
+        decompile(
+            "GETGLOBAL 1 K(1)\nGETGLOBAL 0 K(0)\nTEST 0 0\nJMP 0 1\nGETGLOBAL 1 K(2)\n\
+             SETGLOBAL 1 K(0)\n"
+        ),
+        "local l_0_1 = x\nif f then\n  l_0_1 = y\nend\nf = l_0_1\n"
+    );
+}
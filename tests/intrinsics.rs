@@ -0,0 +1,32 @@
+mod common;
+
+use bungie_lua_decompiler::intrinsics::Intrinsics;
+
+const LISTING: &str = r#"
+.function main
+.vararg 2
+.slots 4
+.const "obj"
+.const "print"
+GETGLOBAL 0 K(0)
+GETGLOBAL 1 K(1)
+INTRINSIC_INDEX 2 0 3
+CALL 1 2 1
+INTRINSIC_SELF 1 0 9
+CALL 1 2 1
+RETURN 0 1
+.end
+"#;
+
+// Known intrinsics read as fields and methods, unknown ones as pseudo-calls.
+#[test]
+fn intrinsics_named_from_table() {
+    let intrinsics = Intrinsics::from_json(r#"{"3": "position"}"#).unwrap();
+    assert_eq!(
+        common::decompile_with(LISTING, &intrinsics),
+        "local l_0_0 = obj\n\
+         print(l_0_0.position)\n\
+         __intrinsic_index(l_0_0, 9)(l_0_0)\n"
+    );
+    assert!(Intrinsics::from_json(r#"{"3": "not a name"}"#).is_err());
+}
@@ -0,0 +1,32 @@
+use bungie_lua_decompiler::json::{to_json, SCHEMA_VERSION};
+use bungie_lua_decompiler::load_chunk;
+use serde_json::Value;
+use std::path::Path;
+
+fn export(name: &str) -> Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(name);
+    let chunk = load_chunk(&std::fs::read(path).unwrap()).unwrap();
+    serde_json::from_str(&to_json(&chunk)).unwrap()
+}
+
+// Every prototype is exported with its decoded instructions and nested
+// prototypes.
+#[test]
+fn json_closures() {
+    let json = export("closures.luac");
+    assert_eq!(json["schema_version"], SCHEMA_VERSION);
+    assert_eq!(json["header"]["version"], 0x51);
+    let main = &json["main_function"];
+    assert_eq!(main["depth"], 0);
+    let instructions = main["instructions"].as_array().unwrap();
+    assert!(instructions
+        .iter()
+        .all(|inst| inst["opcode"].is_string() && inst["args"].is_array()));
+    let children = main["children"].as_array().unwrap();
+    assert!(!children.is_empty());
+    assert!(children.iter().all(|child| child["depth"] == 1));
+    assert_eq!(children[0]["has_debug_info"], true);
+    assert!(children[0]["debug_info"]["lines"].is_array());
+}
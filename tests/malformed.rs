@@ -0,0 +1,72 @@
+mod common;
+
+use bungie_lua_decompiler::asm::assemble;
+use bungie_lua_decompiler::error::Error;
+use bungie_lua_decompiler::load_chunk;
+
+// Counts that size allocations are checked before anything trusts them.
+#[test]
+fn huge_param_count() {
+    let data = assemble(".function main\n.params 4000000000\nRETURN 0 1\n.end\n").unwrap();
+    match load_chunk(&data) {
+        Err(Error::Parse { section, .. }) => assert_eq!(section, "function block"),
+        other => panic!("{:?}", other.map(|_| ())),
+    }
+    let data = assemble(".function main\n.upvalues 4000000000\nRETURN 0 1\n.end\n").unwrap();
+    assert!(matches!(load_chunk(&data), Err(Error::Parse { .. })));
+}
+
+// A truncated file is reported where the data ran out, not where the
+// section it belongs to starts.
+#[test]
+fn truncation_offset() {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/closures.luac");
+    let data = std::fs::read(path).unwrap();
+    let cut = data.len() - 3;
+    match load_chunk(&data[..cut]) {
+        Err(Error::Truncated { section, offset }) => {
+            assert_eq!(section, "function block");
+            assert!(
+                offset + 8 >= cut as u64 && offset <= cut as u64,
+                "{}",
+                offset
+            );
+        }
+        other => panic!("{:?}", other.map(|_| ())),
+    }
+}
+
+// 8-byte ints are only read when they fit the 32 bits they are kept in.
+#[test]
+fn wide_int_overflow() {
+    let mut data = assemble(
+        ".header version=0x51 format=0xE endian=little int_size=8 size_t=8 \
+         instruction_size=4 number_size=8 number_type=float integral_flag=0 unk=0\n\
+         .function main\n.slots 2\n.debug \"@t.lua\" \"\" 0 0\n.local \"wide\" 0 1\n\
+         RETURN 0 1\n.end\n",
+    )
+    .unwrap();
+    assert!(load_chunk(&data).is_ok());
+    let name = data.windows(5).position(|w| w == b"wide\0").unwrap();
+    // The high half of the local's start pc.
+    data[name + 5 + 4] = 1;
+    assert!(matches!(load_chunk(&data), Err(Error::Parse { .. })));
+}
+
+// Loops prepared by the last instruction have no body to enter and are
+// kept as disassembly.
+#[test]
+fn trailing_loop_preparation() {
+    for (prep, looping) in [
+        ("FORPREP 0 -2", "FORLOOP 0 -2"),
+        ("JMP 0 -2", "TFORLOOP 0 1"),
+    ] {
+        let source = common::decompile(&format!(
+            ".function main\n.slots 6\nJMP 0 1\n{}\n{}\n.end\n",
+            looping, prep
+        ));
+        let opcode = looping.split(' ').next().unwrap();
+        assert!(source.contains("unstructured control flow"), "{}", source);
+        assert!(source.contains(opcode), "{}", source);
+    }
+}
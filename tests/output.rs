@@ -0,0 +1,28 @@
+use bungie_lua_decompiler::output::sanitize_source_path;
+use std::path::PathBuf;
+
+#[test]
+fn source_paths_stay_inside_the_output_directory() {
+    let cases = [
+        ("@scripts/ui/hud.lua", "scripts/ui/hud.lua"),
+        ("@C:\\build\\scripts\\ai.lua", "build/scripts/ai.lua"),
+        ("@/abs/../../escape.lua", "abs/escape.lua"),
+        ("=stdin", "stdin.lua"),
+        ("@scripts/bad:name?.lua", "scripts/bad_name_.lua"),
+    ];
+    for (path, expected) in cases {
+        assert_eq!(
+            sanitize_source_path(path),
+            Some(PathBuf::from(expected)),
+            "{}",
+            path
+        );
+    }
+}
+
+#[test]
+fn empty_source_paths_are_rejected() {
+    for path in ["", "@", "@../..", "@C:\\"] {
+        assert_eq!(sanitize_source_path(path), None, "{}", path);
+    }
+}
@@ -0,0 +1,140 @@
+use bungie_lua_decompiler::asm::assemble;
+use bungie_lua_decompiler::disasm::disassemble_chunk;
+use bungie_lua_decompiler::load_chunk;
+use bungie_lua_decompiler::structs::BungieConstantEnum;
+use bungie_lua_decompiler::writer::write_chunk;
+use std::path::Path;
+
+fn round_trip(name: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(name);
+    let data = std::fs::read(&path).unwrap();
+    let chunk = load_chunk(&data).unwrap();
+    let written = write_chunk(&chunk).unwrap();
+    assert_eq!(written.len(), data.len(), "{}: length differs", name);
+    if let Some(offset) = written.iter().zip(&data).position(|(a, b)| a != b) {
+        panic!("{}: first difference at offset 0x{:X}", name, offset);
+    }
+}
+
+#[test]
+fn round_trip_simple() {
+    round_trip("simple.luac");
+}
+
+// 8-byte int, size_t and number.
+#[test]
+fn round_trip_wide_layout() {
+    round_trip("simple_wide.luac");
+}
+
+// Nested prototypes with debug info; every prototype needs alignment
+// padding before its instructions.
+#[test]
+fn round_trip_closures() {
+    round_trip("closures.luac");
+}
+
+#[test]
+fn round_trip_little_endian() {
+    round_trip("closures_le.luac");
+}
+
+// Light userdata is as wide as size_t, 4 bytes here and 8 in the wide
+// file; ui64 constants are 8 bytes in both.
+#[test]
+fn round_trip_userdata() {
+    for (name, pointer) in [
+        ("userdata.luac", &[0x02, 0x12, 0x34, 0x56, 0x78, 0x0B][..]),
+        (
+            "userdata_wide.luac",
+            &[0x02, 0x78, 0x56, 0x34, 0x12, 0, 0, 0, 0, 0x0B][..],
+        ),
+    ] {
+        round_trip(name);
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name);
+        let data = std::fs::read(&path).unwrap();
+        assert!(
+            data.windows(pointer.len()).any(|w| w == pointer),
+            "{}",
+            name
+        );
+        let chunk = load_chunk(&data).unwrap();
+        let constants = &chunk.main_function.consts.constants;
+        assert!(matches!(
+            constants[0].constant,
+            BungieConstantEnum::LightUserData(0x1234_5678)
+        ));
+        assert!(matches!(
+            constants[1].constant,
+            BungieConstantEnum::U64(0x1122_3344_5566_7788)
+        ));
+    }
+}
+
+/// A xorshift generator, so the mutations below are the same every run.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// Any chunk that parses has a canonical form, the bytes its listing
+// assembles to. The canonical form is written back unchanged, and so is
+// its own listing.
+#[test]
+fn canonical_round_trip() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    let mut parsed = 0;
+    for name in [
+        "simple.luac",
+        "simple_wide.luac",
+        "closures.luac",
+        "closures_le.luac",
+        "userdata.luac",
+        "userdata_wide.luac",
+    ] {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name);
+        let original = std::fs::read(&path).unwrap();
+        for _ in 0..2000 {
+            let mut data = original.clone();
+            for _ in 0..1 + rng.below(4) {
+                match rng.below(4) {
+                    0 => data.push(rng.next() as u8),
+                    1 if data.len() > 1 => {
+                        let at = rng.below(data.len());
+                        data.remove(at);
+                    }
+                    _ => {
+                        let at = rng.below(data.len());
+                        data[at] ^= 1 << rng.below(8);
+                    }
+                }
+            }
+            let Ok(chunk) = load_chunk(&data) else {
+                continue;
+            };
+            parsed += 1;
+            let canonical = assemble(&disassemble_chunk(&chunk)).unwrap();
+            let reparsed = load_chunk(&canonical).unwrap();
+            assert_eq!(write_chunk(&reparsed).unwrap(), canonical, "{}", name);
+            let listing = disassemble_chunk(&reparsed);
+            assert_eq!(assemble(&listing).unwrap(), canonical, "{}", name);
+        }
+    }
+    assert!(parsed > 1000, "only {} mutations parsed", parsed);
+}
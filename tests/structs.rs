@@ -0,0 +1,81 @@
+mod common;
+
+use bungie_lua_decompiler::decompiler::{decompile_chunk, lift_function};
+use bungie_lua_decompiler::intrinsics::Intrinsics;
+
+const LISTING: &str = r#"
+.type 12 "Point"
+.function main
+.vararg 2
+.slots 4
+.const "print"
+.const 5
+.debug "@structs.lua" "" 0 0
+.local "p" 1 6
+NEWSTRUCT 0 12 2
+SETSLOT 0 0 K(1)
+GETGLOBAL 1 K(0)
+GETSLOT 2 0 1
+CALL 1 2 1
+SELFSLOT 1 0 0
+CALL 1 2 1
+RETURN 0 1
+.end
+"#;
+
+// Structure types are named after the type constants and declared before
+// the code constructing them.
+#[test]
+fn structs_decompile() {
+    assert_eq!(
+        common::decompile(LISTING),
+        "-- structs.lua\n\
+         -- type 12 Point (engine)\n\
+         hstructure Point\n  slot_0\n  slot_1\nend\n\
+         local p = hmake Point {}\n\
+         p.slot_0 = 5\n\
+         print(p.slot_1)\n\
+         p:slot_0()\n"
+    );
+}
+
+const TYPED: &str = r#"
+.type 12 "Point"
+.function main
+.vararg 2
+.slots 4
+.const 5
+.debug "@types.lua" "" 0 0
+.local "x" 2 5
+.local "p" 4 5
+LOADK 0 K(0)
+CHECKTYPE 0 3
+NEWSTRUCT 1 12 0
+CHECKTYPES 1 12
+RETURN 0 1
+.function main.0
+.params 1
+.slots 2
+.debug "@types.lua" "f" 1 1
+.local "a" 0 2
+CHECKTYPE 0 4
+RETURN 0 1
+.end
+.end
+"#;
+
+// Type checks right after a declaration are folded into it.
+#[test]
+fn typed_declarations() {
+    let chunk = common::load(TYPED);
+    let source = decompile_chunk(&chunk, &Intrinsics::default());
+    assert!(source.contains("local x : number = 5\n"), "{}", source);
+    assert!(
+        source.contains("local p : Point = hmake Point {}\n"),
+        "{}",
+        source
+    );
+    let closure = &chunk.main_function.child_functions[0];
+    let lifted = lift_function(closure);
+    assert_eq!(lifted.params[0].to_string(), "a : string");
+}
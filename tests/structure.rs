@@ -0,0 +1,191 @@
+mod common;
+
+/// Decompiles a one-parameter main function with `consts` running `code`.
+fn structure(consts: &str, code: &str) -> String {
+    common::decompile(&format!(
+        ".function main\n.params 1\n.slots 10\n{}{}RETURN 0 1\n.end\n",
+        consts, code
+    ))
+}
+
+#[test]
+fn if_else() {
+    assert_eq!(
+        structure(
+            ".const \"a\"\n.const \"b\"\n",
+            "TEST 0 0\n\
+             JMP 0 2\n\
+             LOADK 1 K(0)\n\
+             JMP 0 1\n\
+             LOADK 1 K(1)\n\
+             RETURN 1 2\n"
+        ),
+        "local l_0_1\nif l_0_0 then\n  l_0_1 = \"a\"\nelse\n  l_0_1 = \"b\"\n\
+         end\nreturn l_0_1\n"
+    );
+}
+
+// An else arm holding nothing but another conditional reads as elseif.
+#[test]
+fn elseif_chain() {
+    assert_eq!(
+        structure(
+            ".const 10\n.const 20\n.const \"small\"\n.const \"mid\"\n.const \"big\"\n",
+            "LT 0 0 K(0)\n\
+             JMP 0 2\n\
+             LOADK 1 K(2)\n\
+             JMP 0 5\n\
+             LT 0 0 K(1)\n\
+             JMP 0 2\n\
+             LOADK 1 K(3)\n\
+             JMP 0 1\n\
+             LOADK 1 K(4)\n\
+             RETURN 1 2\n"
+        ),
+        "local l_0_1\nif l_0_0 < 10 then\n  l_0_1 = \"small\"\n\
+         elseif l_0_0 < 20 then\n  l_0_1 = \"mid\"\nelse\n  l_0_1 = \"big\"\n\
+         end\nreturn l_0_1\n"
+    );
+}
+
+#[test]
+fn while_loop() {
+    assert_eq!(
+        structure(
+            ".const 0\n.const 1\n",
+            "LOADK 1 K(0)\n\
+             LT 0 1 0\n\
+             JMP 0 2\n\
+             ADD 1 1 K(1)\n\
+             JMP 0 -4\n\
+             RETURN 1 2\n"
+        ),
+        "local l_0_1 = 0\nwhile l_0_1 < l_0_0 do\n  l_0_1 = l_0_1 + 1\nend\n\
+         return l_0_1\n"
+    );
+}
+
+#[test]
+fn repeat_until() {
+    assert_eq!(
+        structure(
+            ".const 1\n.const 0\n",
+            "SUB 0 0 K(0)\n\
+             LE 0 0 K(1)\n\
+             JMP 0 -3\n\
+             RETURN 0 2\n"
+        ),
+        "repeat\n  l_0_0 = l_0_0 - 1\nuntil l_0_0 <= 0\nreturn l_0_0\n"
+    );
+}
+
+#[test]
+fn numeric_for() {
+    assert_eq!(
+        structure(
+            ".const 1\n.const \"print\"\n",
+            "LOADK 1 K(0)\n\
+             MOVE 2 0\n\
+             LOADK 3 K(0)\n\
+             FORPREP 1 3\n\
+             GETGLOBAL 5 K(1)\n\
+             MOVE 6 4\n\
+             CALL 5 2 1\n\
+             FORLOOP 1 -4\n"
+        ),
+        "for l_0_4 = 1, l_0_0 do\n  print(l_0_4)\nend\n"
+    );
+}
+
+#[test]
+fn generic_for() {
+    assert_eq!(
+        structure(
+            ".const \"pairs\"\n.const \"print\"\n",
+            "GETGLOBAL 1 K(0)\n\
+             MOVE 2 0\n\
+             CALL 1 2 4\n\
+             JMP 0 4\n\
+             GETGLOBAL 6 K(1)\n\
+             MOVE 7 4\n\
+             MOVE 8 5\n\
+             CALL 6 3 1\n\
+             TFORLOOP 1 2\n\
+             JMP 0 -6\n"
+        ),
+        "for l_0_4, l_0_5 in pairs(l_0_0) do\n  print(l_0_4, l_0_5)\nend\n"
+    );
+}
+
+// A jump out of an endless loop to the code after it is a break.
+#[test]
+fn break_out_of_loop() {
+    assert_eq!(
+        structure(
+            ".const \"f\"\n.const 1\n.const 10\n",
+            "ADD 0 0 K(1)\n\
+             EQ 1 0 K(2)\n\
+             JMP 0 3\n\
+             GETGLOBAL 1 K(0)\n\
+             CALL 1 1 1\n\
+             JMP 0 -6\n\
+             RETURN 0 2\n"
+        ),
+        "while true do\n  l_0_0 = l_0_0 + 1\n  if l_0_0 == 10 then\n    break\n  end\n  f()\n\
+         end\nreturn l_0_0\n"
+    );
+}
+
+// A cycle entered at two blocks cannot be structured and is kept as
+// commented disassembly.
+#[test]
+fn unstructured_fallback() {
+    assert_eq!(
+        structure(
+            ".const 1\n",
+            "TEST 0 0\n\
+             JMP 0 3\n\
+             ADD 1 1 K(0)\n\
+             TEST 1 0\n\
+             JMP 0 2\n\
+             ADD 1 1 K(0)\n\
+             JMP 0 -5\n"
+        ),
+        "-- unstructured control flow, disassembly follows:\n\
+         -- 0 [-] TEST                       0 0\n\
+         -- 1 [-] JMP                        0 3             ; to 5\n\
+         -- 2 [-] ADD                        1 1 K(0)        ; K(0) = 1\n\
+         -- 3 [-] TEST                       1 0\n\
+         -- 4 [-] JMP                        0 2             ; to 7\n\
+         -- 5 [-] ADD                        1 1 K(0)        ; K(0) = 1\n\
+         -- 6 [-] JMP                        0 -5            ; to 2\n\
+         -- end of unstructured control flow\n"
+    );
+}
+
+// A FORPREP jumping to code before its FORLOOP leaves a header the loop
+// statement cannot hold, so the loop is kept as disassembly instead of
+// dropping that code.
+#[test]
+fn loop_header_with_code() {
+    assert_eq!(
+        structure(
+            ".const 1\n.const 10\n.const \"print\"\n",
+            "LOADK 1 K(0)\n\
+             LOADK 2 K(1)\n\
+             LOADK 3 K(0)\n\
+             FORPREP 1 2\n\
+             GETGLOBAL 5 K(2)\n\
+             CALL 5 1 1\n\
+             SETGLOBAL 4 K(2)\n\
+             FORLOOP 1 -4\n"
+        ),
+        "local l_0_1 = 1\nlocal l_0_2 = 10\nlocal l_0_3 = 1\n\
+         -- unstructured control flow, disassembly follows:\n\
+         -- 4 [-] GETGLOBAL                  5 K(2)          ; K(2) = \"print\"\n\
+         -- 5 [-] CALL                       5 1 1\n\
+         -- 6 [-] SETGLOBAL                  4 K(2)          ; K(2) = \"print\"\n\
+         -- 7 [-] FORLOOP                    1 -4            ; to 4\n\
+         -- end of unstructured control flow\n"
+    );
+}
@@ -0,0 +1,50 @@
+use bungie_lua_decompiler::asm::assemble;
+use bungie_lua_decompiler::disasm::disassemble_chunk;
+use bungie_lua_decompiler::load_chunk;
+use bungie_lua_decompiler::types::{TypeKind, TypeRegistry};
+
+const LISTING: &str = r#"
+.type 12 "Point"
+.type 3 "number"
+.type 12 "Vector"
+.function main
+.vararg 2
+.slots 2
+RETURN 0 1
+.end
+"#;
+
+// The registry keeps the first name of every id, in id order, and the
+// kinds it gives survive a disassembly round trip as comments.
+#[test]
+fn type_registry() {
+    let data = assemble(LISTING).unwrap();
+    let chunk = load_chunk(&data).unwrap();
+    let types = TypeRegistry::new(&chunk.type_constants);
+    let entries: Vec<_> = types
+        .iter()
+        .map(|entry| (entry.id, entry.name.as_str(), entry.kind))
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            (3, "number", TypeKind::Builtin),
+            (12, "Point", TypeKind::Engine)
+        ]
+    );
+    assert_eq!(types.annotation(5), "table");
+    assert_eq!(types.struct_name(13), "struct_13");
+
+    let listing = disassemble_chunk(&chunk);
+    assert!(
+        listing.contains(".type 12 \"Point\" ; engine\n"),
+        "{}",
+        listing
+    );
+    assert!(
+        listing.contains(".type 12 \"Vector\" ; shadowed\n"),
+        "{}",
+        listing
+    );
+    assert_eq!(assemble(&listing).unwrap(), data);
+}